@@ -65,7 +65,9 @@ async function createSiteList () {
   })
 
   // Obtain a list of sites and profiles
+  // Recently used sites are shown first, and sites that were never launched last
   const sites = Object.values(await obtainSiteList())
+    .sort((a, b) => (b.usage?.last_launched || 0) - (a.usage?.last_launched || 0))
   const profiles = await obtainProfileList()
 
   // Get the list elements
@@ -793,16 +795,19 @@ async function handleSettings (hasChanged = false) {
     document.getElementById('settings-use-xinput2').checked = config.runtime_use_xinput2
     document.getElementById('settings-use-portals').checked = config.runtime_use_portals
     document.getElementById('settings-always-patch').checked = config.always_patch
+    document.getElementById('settings-open-out-of-scope').checked = config.open_out_of_scope_urls
 
     // Enable settings inputs
     document.getElementById('settings-enable-wayland-container').title = ''
     document.getElementById('settings-use-xinput2-container').title = ''
     document.getElementById('settings-use-portals-container').title = ''
     document.getElementById('settings-always-patch-container').title = ''
+    document.getElementById('settings-open-out-of-scope-container').title = ''
     document.getElementById('settings-enable-wayland').disabled = false
     document.getElementById('settings-use-xinput2').disabled = false
     document.getElementById('settings-use-portals').disabled = false
     document.getElementById('settings-always-patch').disabled = false
+    document.getElementById('settings-open-out-of-scope').disabled = false
 
     // Helper function to update config
     // Listen for enable Wayland changes
@@ -828,6 +833,12 @@ async function handleSettings (hasChanged = false) {
       config.always_patch = this.checked
       await setConfig(config)
     })
+
+    // Listen for out-of-scope URL changes
+    document.getElementById('settings-open-out-of-scope').addEventListener('change', async function () {
+      config.open_out_of_scope_urls = this.checked
+      await setConfig(config)
+    })
   })
 
   // Hide runtime reinstallation button on unsupported platforms
@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::remove_dir_all;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+use url::Url;
+use web_app_manifest::resources::IconResource;
+use web_app_manifest::types::Url as ManifestUrl;
+
+use crate::components::profile::Profile;
+use crate::components::site::{ManifestCache, Site, SiteConfig, SiteManifest, SiteUsage};
+use crate::directories::ProjectDirs;
+use crate::integrations;
+use crate::integrations::utils::download_icon;
+use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::storage::Storage;
+
+/// The current version of the bundle format.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Contains profile metadata stored in the bundle.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct BundleProfile {
+    /// An original profile ID.
+    ///
+    /// Only used to reference the profile from web apps in the bundle.
+    /// Profiles get a new ID when they are imported, except the default
+    /// profile that is always mapped to the existing default profile.
+    pub id: Ulid,
+
+    /// A profile name.
+    pub name: Option<String>,
+
+    /// A profile description.
+    pub description: Option<String>,
+
+    /// Arguments to be passed to the Firefox runtime for this profile.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime for this profile.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Contains web app data stored in the bundle.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct BundleSite {
+    /// An original ID of the profile where this web app is installed.
+    pub profile: Ulid,
+
+    /// A web app config.
+    pub config: SiteConfig,
+
+    /// A web app manifest.
+    pub manifest: SiteManifest,
+
+    /// A cached raw web app manifest.
+    ///
+    /// Contains manifest members that are not part of the processed
+    /// manifest, such as the `id` used to find duplicates.
+    #[serde(default)]
+    pub cache: ManifestCache,
+
+    /// Cached web app icons stored as data URLs.
+    ///
+    /// If present, they are used for the system integration instead of
+    /// downloading icons when the web app is imported.
+    #[serde(default)]
+    pub icons: Vec<IconResource>,
+}
+
+/// A portable bundle of web apps and their profiles.
+///
+/// Bundles do not contain web app IDs or other machine-specific data, such
+/// as profile directories, so they can be used to move web apps between
+/// machines. Original profile IDs are only used as references inside the
+/// bundle and are never reused when importing it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Bundle {
+    /// A version of the bundle format.
+    pub version: u32,
+
+    /// Profiles of the bundled web apps.
+    pub profiles: Vec<BundleProfile>,
+
+    /// Bundled web apps.
+    pub sites: Vec<BundleSite>,
+}
+
+/// Profiles and web apps imported from a bundle.
+///
+/// Importing downloads icons, so it is done on an unlocked copy of the
+/// storage. Imported profiles and web apps need to be added to the storage
+/// once it is loaded again.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Imported {
+    /// Newly created profiles.
+    pub profiles: Vec<Profile>,
+
+    /// Imported web apps.
+    pub sites: Vec<Site>,
+}
+
+impl Imported {
+    /// Adds imported profiles and web apps to the storage and returns IDs of imported web apps.
+    pub fn insert_into(self, storage: &mut Storage) -> Result<Vec<Ulid>> {
+        for profile in self.profiles {
+            storage.profiles.insert(profile.ulid, profile);
+        }
+
+        let mut ulids = vec![];
+        for site in self.sites {
+            ulids.push(site.ulid);
+            storage.insert_site(site)?;
+        }
+
+        Ok(ulids)
+    }
+
+    /// Removes system integration of imported web apps and directories of imported profiles.
+    ///
+    /// Used to clean up after a failed import. Errors are only logged,
+    /// so cleaning up continues with other web apps and profiles.
+    fn remove(&self, dirs: &ProjectDirs, system_integration: bool) {
+        if system_integration {
+            for site in &self.sites {
+                if let Err(error) =
+                    integrations::uninstall(&IntegrationUninstallArgs { site, dirs })
+                {
+                    warn!("Failed to uninstall system integration of {}: {:?}", site.ulid, error);
+                }
+            }
+        }
+
+        for profile in &self.profiles {
+            let directory = dirs.userdata.join("profiles").join(profile.ulid.to_string());
+            if let Err(error) = remove_dir_all(directory) {
+                warn!("Failed to remove directory of profile {}: {}", profile.ulid, error);
+            }
+        }
+    }
+}
+
+impl Bundle {
+    /// Creates a bundle from web apps in the storage.
+    ///
+    /// If no web app IDs are provided, all web apps are included. Only profiles
+    /// of included web apps are added to the bundle. If the client is provided,
+    /// web app icons are downloaded and cached in the bundle.
+    pub fn new(storage: &Storage, sites: &[Ulid], client: Option<&Client>) -> Result<Self> {
+        let sites: Vec<&Site> = if sites.is_empty() {
+            storage.sites.values().collect()
+        } else {
+            sites
+                .iter()
+                .map(|id| storage.sites.get(id).context("Web app does not exist"))
+                .collect::<Result<_>>()?
+        };
+
+        let mut profiles: Vec<BundleProfile> = vec![];
+        let mut bundled = vec![];
+
+        for site in sites {
+            if !profiles.iter().any(|profile| profile.id == site.profile) {
+                let profile =
+                    storage.profiles.get(&site.profile).context("Web app with invalid profile")?;
+
+                profiles.push(BundleProfile {
+                    id: profile.ulid,
+                    name: profile.name.clone(),
+                    description: profile.description.clone(),
+                    arguments: profile.arguments.clone(),
+                    variables: profile.variables.clone(),
+                });
+            }
+
+            let icons = match client {
+                Some(client) => {
+                    info!("Caching icons of web app {}", site.ulid);
+                    cache_icons(site, client)
+                }
+                None => vec![],
+            };
+
+            bundled.push(BundleSite {
+                profile: site.profile,
+                config: site.config.clone(),
+                manifest: site.manifest.clone(),
+                cache: site.cache.clone(),
+                icons,
+            });
+        }
+
+        Ok(Self { version: BUNDLE_VERSION, profiles, sites: bundled })
+    }
+
+    /// Imports the bundle and returns imported profiles and web apps.
+    ///
+    /// All profiles, except the default profile, are recreated with new IDs,
+    /// and all web apps are installed with new IDs into their profiles. The
+    /// default profile is kept unchanged. Directories of new profiles are created
+    /// with the template, if provided. Web apps that are already installed in their
+    /// profile are skipped. If importing fails, already imported web apps are removed.
+    ///
+    /// The storage is only used to check the policy and find duplicates, and
+    /// imported data need to be added to it with [`Imported::insert_into`].
+    pub fn import(
+        self,
+        storage: &Storage,
+        dirs: &ProjectDirs,
+        client: &Client,
+        system_integration: bool,
+        template: Option<&Path>,
+    ) -> Result<Imported> {
+        if self.version > BUNDLE_VERSION {
+            bail!("Bundle version {} is not supported, update the native program", self.version);
+        }
+
+        if let Some(site) = self
+            .sites
+            .iter()
+            .find(|site| !self.profiles.iter().any(|profile| profile.id == site.profile))
+        {
+            bail!("Bundle contains web app with unknown profile {}", site.profile);
+        }
+
+        for site in &self.sites {
+            storage.policy.check_site(&site.config, &site.manifest)?;
+        }
+
+        info!("Creating profiles");
+        let mut profiles = BTreeMap::new();
+        let mut imported = Imported { profiles: vec![], sites: vec![] };
+
+        for bundled in self.profiles {
+            let ulid = if bundled.id.is_nil() {
+                Ulid::nil()
+            } else {
+                let mut profile = Profile::new(bundled.name, bundled.description);
+                profile.arguments = bundled.arguments;
+                profile.variables = bundled.variables;
+
+                let ulid = profile.ulid;
+                imported.profiles.push(profile);
+                ulid
+            };
+
+            profiles.insert(bundled.id, ulid);
+        }
+
+        // Wrapped into a closure to emulate currently unstable `try` blocks
+        let sites = self.sites;
+        let import = || -> Result<()> {
+            for profile in &imported.profiles {
+                profile.create_directory(dirs, template)?;
+            }
+
+            info!("Installing web apps");
+
+            for bundled in sites {
+                let site = Site {
+                    ulid: Ulid::new(),
+                    profile: profiles[&bundled.profile],
+                    config: bundled.config,
+                    manifest: bundled.manifest,
+                    cache: bundled.cache,
+                    pending_manifest: None,
+                    latest_cache: None,
+                    history: vec![],
+                    pinned: false,
+                    failed_icons: false,
+                    usage: SiteUsage::default(),
+                };
+
+                // Web apps can already be installed in the default profile or listed twice
+                let sites = storage.sites.values().chain(&imported.sites);
+                if let Some(existing) = site.find_duplicate(sites, &site.profile) {
+                    warn!("Web app is already installed in its profile as {}, skipping", existing);
+                    continue;
+                }
+
+                // Web apps are added before integrating them, so they are also cleaned up on failure
+                imported.sites.push(site.clone());
+
+                if system_integration {
+                    // Cached icons are only used for the system integration
+                    // They will be replaced with normal icons once the web app is updated
+                    let mut integrated = site.clone();
+                    if !bundled.icons.is_empty() {
+                        integrated.config.icon_url = None;
+                        integrated.manifest.icons = bundled.icons;
+                    }
+
+                    integrations::install(&IntegrationInstallArgs {
+                        site: &integrated,
+                        dirs,
+                        client: Some(client),
+                        update_manifest: true,
+                        update_icons: true,
+                        old_name: None,
+                    })
+                    .context("Failed to install system integration")?;
+                }
+
+                info!("Web app imported: {}", site.ulid);
+            }
+
+            Ok(())
+        };
+
+        // Partially imported bundles would leave system integration and profile directories behind
+        if let Err(error) = import() {
+            warn!("Failed to import web apps, removing already imported ones");
+            imported.remove(dirs, system_integration);
+            return Err(error);
+        }
+
+        Ok(imported)
+    }
+}
+
+/// Downloads web app icons and converts them to data URLs.
+///
+/// Icons that cannot be downloaded are skipped, so the system
+/// integration will download them again when importing.
+fn cache_icons(site: &Site, client: &Client) -> Vec<IconResource> {
+    let mut icons = vec![];
+
+    for icon in site.icons() {
+        let url: Url = match icon.src.clone().try_into() {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+
+        match download_icon(url.clone(), client) {
+            Ok((bytes, r#type)) => {
+                let data = format!("data:{},{}", r#type, urlencoding::encode_binary(&bytes));
+                let data = Url::parse(&data).context("Failed to create icon data URL");
+
+                match data {
+                    Ok(data) => {
+                        icons.push(IconResource { src: ManifestUrl::Absolute(data), ..icon })
+                    }
+                    Err(error) => warn!("{:?}", error),
+                }
+            }
+            Err(error) => warn!("Failed to download icon {}: {:?}", url, error),
+        }
+    }
+
+    icons
+}
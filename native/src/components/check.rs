@@ -0,0 +1,282 @@
+use std::convert::TryInto;
+
+use log::info;
+use reqwest::blocking::Client;
+use serde::Serialize;
+use url::Url;
+use web_app_manifest::resources::IconResource;
+use web_app_manifest::types::{ImagePurpose, ImageSize, Url as ManifestUrl};
+
+use crate::components::site::{within_scope, Site, SiteConfig, SiteManifest};
+use crate::integrations::utils::inspect_icon;
+
+/// The smallest icon size that is used by all system integrations.
+const MINIMUM_ICON_SIZE: u32 = 48;
+
+/// Protocol schemes that can be handled without the `web+` prefix.
+///
+/// See the [HTML specification](https://html.spec.whatwg.org/multipage/system-state.html#safelisted-scheme).
+const SAFELISTED_SCHEMES: [&str; 24] = [
+    "bitcoin",
+    "ftp",
+    "ftps",
+    "geo",
+    "im",
+    "irc",
+    "ircs",
+    "magnet",
+    "mailto",
+    "matrix",
+    "mms",
+    "news",
+    "nntp",
+    "openpgp4fpr",
+    "sftp",
+    "sip",
+    "sms",
+    "smsto",
+    "ssh",
+    "tel",
+    "urn",
+    "webcal",
+    "wtai",
+    "xmpp",
+];
+
+/// A kind of problem found by the web app checker.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ManifestProblemKind {
+    /// Manifest cannot be downloaded, parsed or processed.
+    InvalidManifest,
+
+    /// Neither the manifest nor the config provides a name.
+    MissingName,
+
+    /// Start URL is relative or invalid.
+    InvalidStartUrl,
+
+    /// Scope is relative or invalid.
+    InvalidScope,
+
+    /// Start URL is not within the scope.
+    StartUrlOutsideScope,
+
+    /// Icon cannot be downloaded or decoded.
+    BrokenIcon,
+
+    /// No working icon can be used at the minimum icon size.
+    MissingIcon,
+
+    /// Protocol handler does not satisfy the specification.
+    InvalidProtocolHandler,
+}
+
+/// A problem found by the web app checker.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestProblem {
+    /// A kind of the problem.
+    pub kind: ManifestProblemKind,
+
+    /// A human-readable description of the problem.
+    pub description: String,
+}
+
+impl ManifestProblem {
+    #[inline]
+    fn new(kind: ManifestProblemKind, description: impl Into<String>) -> Self {
+        Self { kind, description: description.into() }
+    }
+
+    /// Checks the processed manifest and returns all found problems.
+    ///
+    /// If the web app config is provided, its overwrites of the name,
+    /// start URL and icon are checked instead of the manifest values.
+    /// All icons are downloaded and decoded to check that they work.
+    pub fn check(
+        manifest: &SiteManifest,
+        config: Option<&SiteConfig>,
+        client: &Client,
+    ) -> Vec<Self> {
+        let mut problems = vec![];
+
+        let name = config.and_then(|config| config.name.as_ref());
+        if name.or(manifest.name.as_ref()).or(manifest.short_name.as_ref()).is_none() {
+            problems
+                .push(Self::new(ManifestProblemKind::MissingName, "Web app does not have a name"));
+        }
+
+        let scope = match &manifest.scope {
+            ManifestUrl::Absolute(scope) => Some(scope),
+            scope => {
+                let description = format!("Scope {} is not a valid absolute URL", describe(scope));
+                problems.push(Self::new(ManifestProblemKind::InvalidScope, description));
+                None
+            }
+        };
+
+        let start_url =
+            match (config.and_then(|config| config.start_url.as_ref()), &manifest.start_url) {
+                (Some(url), _) | (None, ManifestUrl::Absolute(url)) => Some(url),
+                (None, start_url) => {
+                    let description =
+                        format!("Start URL {} is not a valid absolute URL", describe(start_url));
+                    problems.push(Self::new(ManifestProblemKind::InvalidStartUrl, description));
+                    None
+                }
+            };
+
+        if let (Some(start_url), Some(scope)) = (start_url, scope) {
+            if !within_scope(start_url, scope) {
+                let description = format!("Start URL {} is not within scope {}", start_url, scope);
+                problems.push(Self::new(ManifestProblemKind::StartUrlOutsideScope, description));
+            }
+        }
+
+        let icons = match config.and_then(|config| config.icon_url.as_ref()) {
+            Some(icon) => vec![IconResource {
+                src: ManifestUrl::Absolute(icon.clone()),
+                sizes: [ImageSize::default()].iter().cloned().collect(),
+                purpose: [ImagePurpose::default()].iter().cloned().collect(),
+                r#type: None,
+                label: None,
+            }],
+            None => manifest.icons.clone(),
+        };
+        problems.extend(check_icons(&icons, client));
+
+        for handler in &manifest.protocol_handlers {
+            if let Err(description) = check_protocol_handler(&handler.protocol, &handler.url, scope)
+            {
+                problems.push(Self::new(ManifestProblemKind::InvalidProtocolHandler, description));
+            }
+        }
+
+        problems
+    }
+
+    /// Downloads the manifest and checks it.
+    ///
+    /// If the manifest cannot be downloaded, parsed or processed,
+    /// only that problem is returned.
+    pub fn check_url(manifest_url: &Url, document_url: &Url, client: &Client) -> Vec<Self> {
+        match Site::fetch_manifest(manifest_url, document_url, client) {
+            Ok((manifest, _)) => Self::check(&manifest, None, client),
+            Err(error) => {
+                vec![Self::new(ManifestProblemKind::InvalidManifest, format!("{:#}", error))]
+            }
+        }
+    }
+}
+
+#[inline]
+fn describe(url: &ManifestUrl) -> String {
+    match url {
+        ManifestUrl::Absolute(url) => url.to_string(),
+        ManifestUrl::Relative(url) => format!("`{}`", url),
+        ManifestUrl::Unknown => "`(unknown)`".into(),
+    }
+}
+
+/// Downloads and decodes all icons and checks that at least one can be used.
+fn check_icons(icons: &[IconResource], client: &Client) -> Vec<ManifestProblem> {
+    let mut problems = vec![];
+    let mut usable = false;
+
+    for icon in icons {
+        let url: Url = match icon.src.clone().try_into() {
+            Ok(url) => url,
+            Err(_) => {
+                let description =
+                    format!("Icon {} is not a valid absolute URL", describe(&icon.src));
+                problems.push(ManifestProblem::new(ManifestProblemKind::BrokenIcon, description));
+                continue;
+            }
+        };
+
+        info!("Checking icon {}", url);
+        match inspect_icon(url.clone(), client) {
+            // SVG icons can be rendered at any size, and raster icons only need to be large enough
+            Ok(size) => {
+                let large = match size {
+                    Some((width, height)) => width.max(height) >= MINIMUM_ICON_SIZE,
+                    None => true,
+                };
+                usable |= large && icon.purpose.contains(&ImagePurpose::Any);
+            }
+            Err(error) => {
+                let description = format!("Icon {} cannot be used: {:#}", url, error);
+                problems.push(ManifestProblem::new(ManifestProblemKind::BrokenIcon, description));
+            }
+        }
+    }
+
+    if !usable {
+        let description = format!(
+            "Web app does not have a working icon with the `any` purpose and at least {}px",
+            MINIMUM_ICON_SIZE
+        );
+        problems.push(ManifestProblem::new(ManifestProblemKind::MissingIcon, description));
+    }
+
+    problems
+}
+
+/// Checks the protocol handler according to the manifest and HTML specifications.
+///
+/// The scheme must be safelisted or start with `web+` followed by lowercase ASCII
+/// letters, and the handler URL must be an HTTP(S) URL within the web app scope
+/// that contains the `%s` placeholder.
+fn check_protocol_handler(
+    protocol: &str,
+    url: &ManifestUrl,
+    scope: Option<&Url>,
+) -> Result<(), String> {
+    let protocol = protocol.to_ascii_lowercase();
+    let custom = match protocol.strip_prefix("web+") {
+        Some(name) => !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase()),
+        None => false,
+    };
+
+    if !custom && !SAFELISTED_SCHEMES.contains(&protocol.as_str()) {
+        return Err(format!(
+            "Protocol handler scheme `{}` is not safelisted and does not start with `web+`",
+            protocol
+        ));
+    }
+
+    let url = match url {
+        ManifestUrl::Absolute(url) => url,
+        url => {
+            return Err(format!(
+                "Protocol handler URL {} for `{}` is not a valid absolute URL",
+                describe(url),
+                protocol
+            ))
+        }
+    };
+
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(format!(
+            "Protocol handler URL {} for `{}` is not an HTTP(S) URL",
+            url, protocol
+        ));
+    }
+
+    if !url.as_str().contains("%s") {
+        return Err(format!(
+            "Protocol handler URL {} for `{}` does not contain the `%s` placeholder",
+            url, protocol
+        ));
+    }
+
+    if let Some(scope) = scope {
+        if !within_scope(url, scope) {
+            return Err(format!(
+                "Protocol handler URL {} for `{}` is not within scope",
+                url, protocol
+            ));
+        }
+    }
+
+    Ok(())
+}
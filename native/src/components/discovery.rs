@@ -0,0 +1,184 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use reqwest::blocking::Client;
+use scraper::{ElementRef, Html, Selector};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use url::Url;
+
+const DOWNLOAD_ERROR: &str = "Failed to download the page";
+const PARSE_ERROR: &str = "Failed to parse the page";
+
+/// Link types of page icons that can be used in the synthesized manifest.
+const ICON_RELS: &[&str] =
+    &["icon", "apple-touch-icon", "apple-touch-icon-precomposed", "mask-icon"];
+
+/// Contains URLs of a web app discovered from its page.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct Discovery {
+    /// URL of the page after following all redirects.
+    pub document_url: Url,
+
+    /// URL of the web app manifest.
+    ///
+    /// Either linked from the page, or a data URL
+    /// with the manifest synthesized from the page.
+    pub manifest_url: Url,
+
+    /// Whether the manifest was synthesized from the page.
+    pub synthesized: bool,
+}
+
+impl Discovery {
+    /// Downloads the page and discovers its web app manifest.
+    ///
+    /// The manifest URL is taken from the first `<link rel="manifest">` element
+    /// with a non-empty `href` and resolved against the document base URL,
+    /// which respects the `<base>` element if the page contains one.
+    ///
+    /// If the page does not link a manifest and synthesizing is enabled, a
+    /// manifest is generated from the page metadata and stored as a data URL.
+    pub fn new(page: &Url, client: &Client, synthesize: bool) -> Result<Self> {
+        info!("Downloading the page");
+        let response = client
+            .get(page.to_owned())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .context(DOWNLOAD_ERROR)?;
+
+        let document_url = response.url().to_owned();
+        let html = response.text().context(DOWNLOAD_ERROR)?;
+
+        info!("Discovering the web app manifest");
+        let document = Html::parse_document(&html);
+
+        // Only the first base element with an href attribute is used
+        let base = match select(&document, "base[href]").next().and_then(|base| attr(base, "href"))
+        {
+            Some(href) => document_url.join(href).context(PARSE_ERROR)?,
+            None => document_url.clone(),
+        };
+
+        let link = select(&document, "link[rel][href]")
+            .find(|link| has_rel(link, "manifest") && attr(*link, "href").is_some());
+
+        let link = match link {
+            Some(link) => link,
+            None if synthesize => {
+                info!("Synthesizing the web app manifest from the page");
+                let manifest_url = synthesize_manifest(&document, &base, &document_url)?;
+                return Ok(Self { document_url, manifest_url, synthesized: true });
+            }
+            None => bail!(
+                "Page {} does not link a web app manifest, install it with a direct manifest URL or synthesize the manifest from the page",
+                document_url
+            ),
+        };
+
+        let manifest_url = base.join(attr(link, "href").unwrap_or_default());
+        let manifest_url = manifest_url.context("Page links an invalid web app manifest URL")?;
+
+        // Browsers only send credentials with cross-origin manifest requests if the page asks for it
+        // The native program does not have access to browser cookies, so such manifests may not load
+        let credentials =
+            attr(link, "crossorigin").map(|mode| mode.eq_ignore_ascii_case("use-credentials"));
+        if credentials == Some(true) && manifest_url.origin() != document_url.origin() {
+            warn!("Web app manifest requires credentials, which are not available outside the browser");
+        }
+
+        info!("Web app manifest discovered: {}", manifest_url);
+        Ok(Self { document_url, manifest_url, synthesized: false })
+    }
+}
+
+#[inline]
+fn select<'a>(document: &'a Html, selector: &str) -> impl Iterator<Item = ElementRef<'a>> {
+    let selector = Selector::parse(selector).unwrap();
+    document.select(&selector).collect::<Vec<_>>().into_iter()
+}
+
+/// Returns the trimmed attribute value, or `None` if it is missing or empty.
+#[inline]
+fn attr<'a>(element: ElementRef<'a>, name: &str) -> Option<&'a str> {
+    element.value().attr(name).map(str::trim).filter(|value| !value.is_empty())
+}
+
+/// Checks whether the link contains the relation, matching it case-insensitively.
+#[inline]
+fn has_rel(link: &ElementRef, rel: &str) -> bool {
+    let rels = link.value().attr("rel").unwrap_or_default();
+    rels.split_ascii_whitespace().any(|value| value.eq_ignore_ascii_case(rel))
+}
+
+/// Returns the content of the first meta element with the name.
+fn meta<'a>(document: &'a Html, name: &str) -> Option<&'a str> {
+    select(document, "meta[name][content]")
+        .find(|meta| meta.value().attr("name").unwrap_or_default().eq_ignore_ascii_case(name))
+        .and_then(|meta| attr(meta, "content"))
+}
+
+/// Generates the web app manifest from the page metadata and returns it as a data URL.
+///
+/// Follows the same rules as the browser extension when installing sites without
+/// a manifest. The name is taken from the `application-name` meta element or the
+/// page title, and all icon links, including Apple touch and mask icons, are used.
+fn synthesize_manifest(document: &Html, base: &Url, document_url: &Url) -> Result<Url> {
+    let title = select(document, "title").next().map(|title| title.text().collect::<String>());
+    let title = title.as_deref().map(str::trim).filter(|title| !title.is_empty());
+
+    let name = meta(document, "application-name")
+        .or(title)
+        .or_else(|| document_url.host_str())
+        .unwrap_or_else(|| document_url.as_str());
+
+    let mut manifest = Map::new();
+    manifest.insert("start_url".into(), document_url.as_str().into());
+    manifest.insert("name".into(), name.into());
+
+    if let Some(description) = meta(document, "description") {
+        manifest.insert("description".into(), description.into());
+    }
+
+    // Prefer the theme color without media queries, as it applies to all color schemes
+    let colors: Vec<_> = select(document, "meta[name][content]")
+        .filter(|meta| {
+            meta.value().attr("name").unwrap_or_default().eq_ignore_ascii_case("theme-color")
+        })
+        .collect();
+    let color = colors.iter().find(|meta| meta.value().attr("media").is_none()).or(colors.first());
+    if let Some(color) = color.and_then(|color| attr(*color, "content")) {
+        manifest.insert("theme_color".into(), color.into());
+    }
+
+    let mut icons = vec![];
+    for link in select(document, "link[rel][href]") {
+        if !ICON_RELS.iter().any(|rel| has_rel(&link, rel)) {
+            continue;
+        }
+
+        let src = match attr(link, "href").and_then(|href| base.join(href).ok()) {
+            Some(src) => src,
+            None => continue,
+        };
+
+        // Apple mask icons are monochrome SVG icons, but do not always specify their type
+        let mask = has_rel(&link, "mask-icon");
+        let r#type = attr(link, "type").or(if mask { Some("image/svg+xml") } else { None });
+
+        let mut icon = json!({
+            "src": src.as_str(),
+            "purpose": if mask { "monochrome" } else { "any" },
+            "sizes": attr(link, "sizes").unwrap_or_default(),
+        });
+        if let Some(r#type) = r#type {
+            icon["type"] = r#type.into();
+        }
+
+        icons.push(icon);
+    }
+    manifest.insert("icons".into(), Value::Array(icons));
+
+    let manifest = serde_json::to_string(&manifest)?;
+    let manifest = format!("data:application/manifest+json,{}", urlencoding::encode(&manifest));
+    Url::parse(&manifest).context("Failed to create manifest data URL")
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeSet;
+use std::fs::{create_dir_all, read_to_string, remove_file};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use ulid::Ulid;
+
+use crate::directories::ProjectDirs;
+use crate::storage::Storage;
+
+/// Contains a running runtime instance of a profile.
+///
+/// All web apps in the same profile share a runtime instance. The runtime is
+/// still started for every launch, but when the profile is already running,
+/// it passes the launch to the running instance using the Firefox remote
+/// mechanism and exits. The running instance then handles the launch based on
+/// the web app client mode. Tracking the instance is needed, so the runtime and
+/// profile are not patched while they are in use.
+///
+/// Instances are stored as JSON files in the `instances` directory of user data,
+/// one for each running profile, which also record the web apps launched in it.
+/// Files of instances that are no longer running are removed when loading them.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Instance {
+    /// Process ID of the runtime.
+    pub pid: u32,
+
+    /// Web apps launched in the instance.
+    ///
+    /// Web apps forwarded to the instance are added when launching them.
+    /// Closed web apps are kept until the whole instance exits.
+    #[serde(default)]
+    pub sites: BTreeSet<Ulid>,
+}
+
+impl Instance {
+    #[inline]
+    fn path(dirs: &ProjectDirs, profile: &Ulid) -> PathBuf {
+        dirs.userdata.join("instances").join(format!("{profile}.json"))
+    }
+
+    /// Loads the running instance of the profile.
+    ///
+    /// Returns `None` if the profile does not have an instance, or if the instance
+    /// is no longer running, in which case its file is also removed.
+    pub fn load(dirs: &ProjectDirs, profile: &Ulid) -> Option<Self> {
+        let path = Self::path(dirs, profile);
+        let directory = dirs.userdata.join("profiles").join(profile.to_string());
+
+        // Another launch could replace the file in the meantime, so it is only removed under the lock
+        Storage::with_lock(dirs, || {
+            let data = read_to_string(&path).ok();
+            match data.and_then(|data| serde_json::from_str::<Self>(&data).ok()) {
+                Some(instance) if instance.is_running(&directory) => Ok(Some(instance)),
+                _ => {
+                    let _ = remove_file(&path);
+                    Ok(None)
+                }
+            }
+        })
+        .ok()
+        .flatten()
+    }
+
+    /// Writes the instance of the profile.
+    ///
+    /// The file is written atomically while holding the storage lock,
+    /// so concurrent launches never read a partially written file.
+    pub fn write(&self, dirs: &ProjectDirs, profile: &Ulid) -> Result<()> {
+        Storage::with_lock(dirs, || self.persist(dirs, profile))
+    }
+
+    /// Records a web app launched in the running instance of the profile.
+    ///
+    /// The instance is read again while holding the storage lock, so web apps
+    /// recorded by concurrent launches are kept. Does nothing if the instance
+    /// has exited in the meantime.
+    pub fn add_site(dirs: &ProjectDirs, profile: &Ulid, site: &Ulid) -> Result<()> {
+        let path = Self::path(dirs, profile);
+
+        Storage::with_lock(dirs, || {
+            let data = match read_to_string(&path) {
+                Ok(data) => data,
+                Err(_) => return Ok(()),
+            };
+
+            let mut instance: Self =
+                serde_json::from_str(&data).context("Failed to parse instance file")?;
+            if instance.sites.insert(*site) {
+                instance.persist(dirs, profile)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn persist(&self, dirs: &ProjectDirs, profile: &Ulid) -> Result<()> {
+        const INSTANCE_WRITE_ERROR: &str = "Failed to write instance file";
+
+        let path = Self::path(dirs, profile);
+        let directory = dirs.userdata.join("instances");
+        create_dir_all(&directory).context("Failed to create instances directory")?;
+
+        let mut file = NamedTempFile::new_in(&directory).context(INSTANCE_WRITE_ERROR)?;
+        file.write_all(serde_json::to_string(self)?.as_bytes()).context(INSTANCE_WRITE_ERROR)?;
+        file.persist(path).context(INSTANCE_WRITE_ERROR)?;
+
+        Ok(())
+    }
+
+    /// Checks whether the runtime process is still running.
+    ///
+    /// On Linux, the process command line also needs to contain the profile
+    /// directory, so reused process IDs are not mistaken for the runtime.
+    #[allow(unused_variables)]
+    fn is_running(&self, directory: &Path) -> bool {
+        cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                let directory = directory.display().to_string();
+                match read_to_string(format!("/proc/{}/cmdline", self.pid)) {
+                    Ok(cmdline) => cmdline.split('\0').any(|arg| arg == directory),
+                    Err(_) => false,
+                }
+            } else if #[cfg(target_os = "windows")] {
+                use windows::Win32::Foundation::{CloseHandle, STILL_ACTIVE};
+                use windows::Win32::System::Threading::{
+                    GetExitCodeProcess,
+                    OpenProcess,
+                    PROCESS_QUERY_LIMITED_INFORMATION,
+                };
+
+                unsafe {
+                    let process = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, self.pid) {
+                        Ok(process) => process,
+                        Err(_) => return false,
+                    };
+
+                    let mut code = 0;
+                    let result = GetExitCodeProcess(process, &mut code).as_bool();
+                    CloseHandle(process);
+
+                    result && code == STILL_ACTIVE.0 as u32
+                }
+            } else {
+                use std::process::{Command, Stdio};
+
+                // Signal 0 only checks whether the process exists
+                let status = Command::new("kill")
+                    .args(["-0", &self.pid.to_string()])
+                    .stderr(Stdio::null())
+                    .status();
+
+                matches!(status, Ok(status) if status.success())
+            }
+        }
+    }
+}
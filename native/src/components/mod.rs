@@ -1,6 +1,10 @@
 #[cfg(target_os = "windows")]
 pub mod _7zip;
 
+pub mod bundle;
+pub mod check;
+pub mod discovery;
+pub mod instance;
 pub mod profile;
 pub mod runtime;
 pub mod site;
@@ -1,4 +1,6 @@
-use std::fs::{create_dir_all, remove_dir_all};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, metadata, remove_dir_all};
+use std::path::Path;
 
 use anyhow::{Context, Result};
 use fs_extra::dir::{copy, CopyOptions};
@@ -28,9 +30,28 @@ pub struct Profile {
     /// A profile description.
     pub description: Option<String>,
 
+    /// A user-chosen profile key.
+    ///
+    /// Only set for profiles managed by a desired-state file and
+    /// used to match them with entries from that file.
+    #[serde(default)]
+    pub key: Option<String>,
+
     /// A list of web app IDs installed within this profile.
     #[serde(default)]
     pub sites: Vec<Ulid>,
+
+    /// Arguments to be passed to the Firefox runtime for this profile.
+    ///
+    /// Passed after the global arguments.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime for this profile.
+    ///
+    /// Overwrite the global variables with the same name.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
 }
 
 impl Default for Profile {
@@ -40,7 +61,10 @@ impl Default for Profile {
             ulid: Ulid::nil(),
             name: Some("Default".into()),
             description: Some("Default profile for all web apps".into()),
+            key: None,
             sites: vec![],
+            arguments: vec![],
+            variables: BTreeMap::new(),
         }
     }
 }
@@ -48,7 +72,59 @@ impl Default for Profile {
 impl Profile {
     #[inline]
     pub fn new(name: Option<String>, description: Option<String>) -> Self {
-        Self { ulid: Ulid::new(), name, description, sites: vec![] }
+        Self {
+            ulid: Ulid::new(),
+            name,
+            description,
+            key: None,
+            sites: vec![],
+            arguments: vec![],
+            variables: BTreeMap::new(),
+        }
+    }
+
+    /// Creates the profile directory and copies the template into it.
+    ///
+    /// All contents of the template directory are copied to the profile directory.
+    pub fn create_directory(&self, dirs: &ProjectDirs, template: Option<&Path>) -> Result<()> {
+        let target = dirs.userdata.join("profiles").join(self.ulid.to_string());
+        create_dir_all(&target).context("Failed to create a profile directory")?;
+
+        if let Some(template) = template {
+            let mut options = CopyOptions::new();
+            options.content_only = true;
+            options.overwrite = true;
+
+            info!("Copying a profile template");
+            copy(template, target, &options).context("Failed to copy a profile template")?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the profile needs to be (re-)patched.
+    ///
+    /// Uses the `chrome.jsm` file because it contains version info. Patching is
+    /// needed if modification dates of the source and target are different. In
+    /// case any error happens (for example, the profile has not been patched yet),
+    /// patching is always needed.
+    pub fn needs_patch(&self, dirs: &ProjectDirs) -> bool {
+        let source = dirs.sysdata.join("userchrome/profile/chrome/pwa/chrome.jsm");
+        let target = dirs
+            .userdata
+            .join("profiles")
+            .join(self.ulid.to_string())
+            .join("chrome/pwa/chrome.jsm");
+
+        if let (Ok(source), Ok(target)) = (metadata(source), metadata(target)) {
+            if let (Ok(source), Ok(target)) = (source.modified(), target.modified()) {
+                source > target
+            } else {
+                true
+            }
+        } else {
+            true
+        }
     }
 
     pub fn patch(&self, dirs: &ProjectDirs) -> Result<()> {
@@ -268,6 +268,19 @@ impl Runtime {
         Ok(())
     }
 
+    /// Checks whether the runtime has been patched with the UserChrome modifications.
+    pub fn is_patched(&self) -> bool {
+        cfg_if! {
+            if #[cfg(target_os = "macos")] {
+                let target = self.directory.join("Firefox.app/Contents/Resources");
+            } else {
+                let target = &self.directory;
+            }
+        }
+
+        target.join("defaults/pref/autoconfig.js").exists()
+    }
+
     #[allow(unused_variables)]
     pub fn patch(&self, dirs: &ProjectDirs, site: &Site) -> Result<()> {
         let source = dirs.sysdata.join("userchrome/runtime");
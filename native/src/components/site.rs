@@ -1,17 +1,22 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::process::Child;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use data_url::DataUrl;
 use log::info;
 use reqwest::blocking::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use ulid::Ulid;
 use url::Url;
 use web_app_manifest::resources::{IconResource, ProtocolHandlerResource};
 use web_app_manifest::types::{ImagePurpose, ImageSize, Url as ManifestUrl};
 pub use web_app_manifest::WebAppManifest as SiteManifest;
 
+use crate::components::profile::Profile;
 use crate::components::runtime::Runtime;
 use crate::directories::ProjectDirs;
 use crate::storage::Config;
@@ -19,7 +24,37 @@ use crate::storage::Config;
 const DOWNLOAD_ERROR: &str = "Failed to download web app manifest";
 const DATA_URL_ERROR: &str = "Failed to process web app manifest data URL";
 const PARSE_ERROR: &str = "Failed to parse web app manifest";
-const INVALID_URL: &str = "Web app without valid absolute URL is not possible";
+const INVALID_START_URL: &str = "Web app does not have a valid absolute start URL";
+const INVALID_SCOPE: &str = "Web app does not have a valid absolute scope with a host";
+
+const SHARE_URLENCODED: &str = "application/x-www-form-urlencoded";
+const SHARE_MULTIPART: &str = "multipart/form-data";
+
+/// Manifest fields that affect the web app identity or security.
+///
+/// Following the W3C guidance on manifest updates, changes of these
+/// fields are not applied until they are accepted by the user. Changes
+/// of `*_localized` members are also sensitive, as they translate the name.
+const SENSITIVE_FIELDS: [&str; 9] = [
+    "name",
+    "short_name",
+    "icons",
+    "scope",
+    "start_url",
+    "id",
+    "file_handlers",
+    "share_target",
+    "launch_handler",
+];
+
+/// Manifest fields that are only read from the raw manifest.
+///
+/// They are not part of the processed manifest, so their changes are found by
+/// comparing raw manifests. The `*_localized` members are also read this way.
+const RAW_FIELDS: [&str; 4] = ["id", "file_handlers", "share_target", "launch_handler"];
+
+/// Number of previous manifests kept in the manifest history of each web app.
+const MANIFEST_HISTORY_SIZE: usize = 5;
 
 /// Contains configuration for the web app.
 ///
@@ -44,6 +79,13 @@ pub struct SiteConfig {
     /// A custom web app icon URL.
     pub icon_url: Option<Url>,
 
+    /// A user-chosen web app key.
+    ///
+    /// Only set for web apps managed by a desired-state file and
+    /// used to match them with entries from that file.
+    #[serde(default)]
+    pub key: Option<String>,
+
     /// Direct URL of the site's main document.
     pub document_url: Url,
 
@@ -56,6 +98,14 @@ pub struct SiteConfig {
     /// Custom web app keywords.
     pub keywords: Option<Vec<String>>,
 
+    /// A locale used for the web app name and description.
+    ///
+    /// Specified as a language tag, such as `de` or `pt-BR`. If set, the
+    /// translations from the localized manifest members are preferred
+    /// over the default manifest values.
+    #[serde(default)]
+    pub locale: Option<String>,
+
     /// Enabled URL handlers.
     ///
     /// Contains web app URL scopes that the browser extension
@@ -83,6 +133,13 @@ pub struct SiteConfig {
     #[serde(default)]
     pub custom_protocol_handlers: Vec<ProtocolHandlerResource>,
 
+    /// Enabled file handlers.
+    ///
+    /// Contains action URLs of web app's file handlers that
+    /// can be used when launching the web app with files.
+    #[serde(default)]
+    pub enabled_file_handlers: Vec<String>,
+
     /// Whether the web app should be launched on the system login.
     #[serde(default)]
     pub launch_on_login: bool,
@@ -90,6 +147,400 @@ pub struct SiteConfig {
     /// Whether the web app should be launched on the browser launch.
     #[serde(default)]
     pub launch_on_browser: bool,
+
+    /// Arguments to be passed to the Firefox runtime for this web app.
+    ///
+    /// Passed after the global and profile arguments.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime for this web app.
+    ///
+    /// Overwrite the global and profile variables with the same name.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+
+    /// Overwrites the Wayland Display Server option from the config.
+    ///
+    /// If not set, the config value is used.
+    #[serde(default)]
+    pub runtime_enable_wayland: Option<bool>,
+
+    /// Overwrites the XInput2 option from the config.
+    ///
+    /// If not set, the config value is used.
+    #[serde(default)]
+    pub runtime_use_xinput2: Option<bool>,
+
+    /// Overwrites the XDG Desktop Portals option from the config.
+    ///
+    /// If not set, the config value is used.
+    #[serde(default)]
+    pub runtime_use_portals: Option<bool>,
+}
+
+/// Contains the cached web app manifest and its HTTP validators.
+///
+/// Validators are sent with conditional requests when updating the manifest,
+/// so it does not need to be downloaded and processed again if it has not
+/// been modified.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+pub struct ManifestCache {
+    /// Value of the `ETag` header of the last manifest response.
+    pub etag: Option<String>,
+
+    /// Value of the `Last-Modified` header of the last manifest response.
+    pub last_modified: Option<String>,
+
+    /// Raw body of the last downloaded manifest.
+    pub body: Option<String>,
+}
+
+/// Contains translations of manifest members for a single locale.
+///
+/// Translations are provided by the `*_localized` manifest members
+/// from the manifest localization proposal.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Default)]
+pub struct SiteTranslation {
+    /// Translation of the web app name.
+    pub name: Option<String>,
+
+    /// Translation of the web app short name.
+    pub short_name: Option<String>,
+
+    /// Translation of the web app description.
+    pub description: Option<String>,
+
+    /// Translation of the web app keywords.
+    pub keywords: Option<Vec<String>>,
+}
+
+/// Determines how launching the web app is handled when it is already running.
+///
+/// See the [`launch_handler`](https://wicg.github.io/web-app-launch/#launch_handler-member)
+/// manifest member. The `auto` mode leaves the behavior to the runtime settings.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ClientMode {
+    /// Focus the existing window without navigating it.
+    FocusExisting,
+
+    /// Navigate the existing window to the launched URL.
+    NavigateExisting,
+
+    /// Open the launched URL in a new window.
+    NavigateNew,
+}
+
+impl ClientMode {
+    /// Returns the name of the client mode, as used in the manifest.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::FocusExisting => "focus-existing",
+            Self::NavigateExisting => "navigate-existing",
+            Self::NavigateNew => "navigate-new",
+        }
+    }
+}
+
+/// Contains usage statistics of a web app.
+///
+/// Statistics are recorded when the web app is launched. Web apps that were
+/// launched before statistics were recorded start with empty statistics.
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone)]
+#[serde(default)]
+pub struct SiteUsage {
+    /// How many times the web app has been launched.
+    pub launches: u64,
+
+    /// Time of the last launch, in milliseconds since the Unix epoch.
+    pub last_launched: Option<u64>,
+
+    /// Total running time of the web app, in seconds.
+    ///
+    /// Only recorded when the launcher waits for the runtime to exit,
+    /// which is currently only the case on macOS.
+    pub running_time: u64,
+}
+
+impl SiteUsage {
+    /// Records a launch at the current time.
+    pub fn record_launch(&mut self) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.launches += 1;
+        self.last_launched = Some(now.as_millis() as u64);
+    }
+}
+
+/// Contains a previous web app manifest from the manifest history.
+///
+/// Only the raw manifest is stored, and it is parsed and
+/// processed again when the web app is rolled back to it.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestRevision {
+    /// Revision ID, which also encodes the time when the manifest was replaced.
+    pub id: Ulid,
+
+    /// Cached manifest of the revision.
+    pub cache: ManifestCache,
+}
+
+/// Contains a file handler declared by the web app manifest.
+///
+/// See the [`file_handlers`] manifest member.
+///
+/// [`file_handlers`]: https://wicg.github.io/manifest-incubations/#file_handlers-member
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct FileHandler {
+    /// URL that is opened to handle the files.
+    ///
+    /// Also used to identify the handler when enabling it.
+    pub action: Url,
+
+    /// Optional name of the handled file type.
+    pub name: Option<String>,
+
+    /// Accepted MIME types and their file extensions (including the leading `.`).
+    pub accept: BTreeMap<String, Vec<String>>,
+}
+
+/// Contains data shared with the web app.
+///
+/// In a share target, contains names of the query or form
+/// parameters that receive the shared values instead.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Default)]
+pub struct ShareData {
+    /// Title of the shared data.
+    pub title: Option<String>,
+
+    /// Text of the shared data.
+    pub text: Option<String>,
+
+    /// URL of the shared data.
+    pub url: Option<String>,
+}
+
+/// HTTP method used to share data with the web app.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ShareMethod {
+    Get,
+    Post,
+}
+
+/// Contains a share target declared by the web app manifest.
+///
+/// See the [Web Share Target](https://w3c.github.io/web-share-target/) specification.
+/// Sharing files is not supported, so their parameters are ignored.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct ShareTarget {
+    /// URL that receives the shared data.
+    pub action: Url,
+
+    /// HTTP method used to send the shared data.
+    pub method: ShareMethod,
+
+    /// Encoding type of the shared data when sent with the `POST` method.
+    pub enctype: String,
+
+    /// Names of parameters that receive the shared data.
+    pub params: ShareData,
+}
+
+impl ShareTarget {
+    /// Returns parameter names with the shared values, skipping missing ones.
+    pub fn entries<'a>(&'a self, data: &'a ShareData) -> Vec<(&'a str, &'a str)> {
+        let fields = vec![
+            (&self.params.title, &data.title),
+            (&self.params.text, &data.text),
+            (&self.params.url, &data.url),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| Some((name.as_deref()?, value.as_deref()?)))
+            .filter(|(_, value)| !value.is_empty())
+            .collect()
+    }
+
+    /// Returns the action URL with the shared data as its query, for the `GET` method.
+    ///
+    /// As required by the specification, the existing query of the action URL is replaced.
+    pub fn query_url(&self, data: &ShareData) -> Url {
+        let mut url = self.action.clone();
+        url.set_query(None);
+        url.query_pairs_mut().extend_pairs(self.entries(data));
+        url
+    }
+
+    /// Returns an HTML document that submits the shared data, for the `POST` method.
+    ///
+    /// Submitting a form is the only way to make the runtime send a `POST` request.
+    pub fn form_document(&self, data: &ShareData) -> String {
+        let inputs: String = self
+            .entries(data)
+            .into_iter()
+            .map(|(name, value)| {
+                format!(
+                    "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+                    escape_html(name),
+                    escape_html(value)
+                )
+            })
+            .collect();
+
+        format!(
+            "<!DOCTYPE html>
+<html>
+<head><meta charset=\"utf-8\"><title>Sharing…</title></head>
+<body>
+<form method=\"post\" action=\"{action}\" enctype=\"{enctype}\">{inputs}<noscript><button>Share</button></noscript></form>
+<script>document.forms[0].submit()</script>
+</body>
+</html>
+",
+            action = escape_html(self.action.as_str()),
+            enctype = escape_html(&self.enctype),
+            inputs = inputs,
+        )
+    }
+}
+
+#[inline]
+fn escape_html(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Checks whether the URL is within the scope, as defined by the manifest specification.
+///
+/// The URL needs to have the same origin as the scope and its path needs to start with the scope path.
+#[inline]
+pub(crate) fn within_scope(url: &Url, scope: &Url) -> bool {
+    url.origin() == scope.origin() && url.path().starts_with(scope.path())
+}
+
+/// Checks whether the MIME type is a valid `type/subtype` essence without parameters.
+fn valid_mime_type(mime: &str) -> bool {
+    let token = |part: &str| {
+        !part.is_empty()
+            && part.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+
+    match mime.split_once('/') {
+        Some((kind, subtype)) => token(kind) && token(subtype),
+        None => false,
+    }
+}
+
+/// Checks whether the file extension starts with `.` and does not contain special characters.
+fn valid_extension(extension: &str) -> bool {
+    match extension.strip_prefix('.') {
+        Some(name) => {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || "-_+.".contains(c))
+        }
+        None => false,
+    }
+}
+
+impl FileHandler {
+    /// Checks whether the handler accepts the file, based on its extension.
+    pub fn accepts(&self, file: &Path) -> bool {
+        let extension = match file.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => format!(".{}", extension.to_lowercase()),
+            None => return false,
+        };
+
+        self.accept.values().flatten().any(|accepted| accepted.to_lowercase() == extension)
+    }
+}
+
+/// Contains a change of a single top-level web app manifest field.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestChange {
+    /// Name of the changed field.
+    pub field: String,
+
+    /// Previous value of the field, or `null` if it was not set.
+    pub old: Value,
+
+    /// New value of the field, or `null` if it was removed.
+    pub new: Value,
+
+    /// Whether the change needs to be accepted before it is applied.
+    pub sensitive: bool,
+}
+
+impl ManifestChange {
+    /// Compares top-level fields of both manifests and returns all changes.
+    ///
+    /// Fields that are not part of the processed manifests are compared
+    /// using their raw manifests, if they are available.
+    pub fn diff(
+        old: &SiteManifest,
+        old_raw: Option<&str>,
+        new: &SiteManifest,
+        new_raw: Option<&str>,
+    ) -> Result<Vec<Self>> {
+        let old = fields(old, old_raw)?;
+        let new = fields(new, new_raw)?;
+
+        let fields: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        let changes = fields
+            .into_iter()
+            .filter(|field| old.get(*field) != new.get(*field))
+            .map(|field| Self {
+                field: field.to_owned(),
+                old: old.get(field).cloned().unwrap_or_default(),
+                new: new.get(field).cloned().unwrap_or_default(),
+                sensitive: is_sensitive(field),
+            })
+            .collect();
+
+        Ok(changes)
+    }
+}
+
+impl Display for ManifestChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Lists and objects (such as icons) are too long to be displayed in full
+        let summary = |value: &Value| match value {
+            Value::Null => "none".into(),
+            Value::Array(items) => format!("[{} items]", items.len()),
+            Value::Object(_) => "{...}".into(),
+            value => value.to_string(),
+        };
+
+        write!(f, "{}: {} -> {}", self.field, summary(&self.old), summary(&self.new))
+    }
+}
+
+#[inline]
+fn to_object(manifest: &SiteManifest) -> Result<Map<String, Value>> {
+    match serde_json::to_value(manifest)? {
+        Value::Object(object) => Ok(object),
+        _ => Ok(Map::new()),
+    }
+}
+
+/// Returns top-level fields of the processed manifest and fields only found in the raw manifest.
+fn fields(manifest: &SiteManifest, raw: Option<&str>) -> Result<Map<String, Value>> {
+    let mut fields = to_object(manifest)?;
+
+    if let Some(Ok(Value::Object(raw))) = raw.map(serde_json::from_str::<Value>) {
+        for (field, value) in raw.into_iter().filter(|(field, _)| is_raw(field)) {
+            fields.entry(field).or_insert(value);
+        }
+    }
+
+    Ok(fields)
+}
+
+#[inline]
+fn is_raw(field: &str) -> bool {
+    RAW_FIELDS.contains(&field) || field.ends_with("_localized")
+}
+
+#[inline]
+fn is_sensitive(field: &str) -> bool {
+    SENSITIVE_FIELDS.contains(&field) || field.ends_with("_localized")
 }
 
 #[non_exhaustive]
@@ -111,17 +562,97 @@ pub struct Site {
 
     /// A web app manifest.
     pub manifest: SiteManifest,
+
+    /// A cached raw web app manifest.
+    ///
+    /// Contains the manifest whose sensitive fields are currently applied, so
+    /// members that are only read from the raw manifest also stay unchanged
+    /// while sensitive changes are pending.
+    #[serde(default)]
+    pub cache: ManifestCache,
+
+    /// A new web app manifest with sensitive changes waiting to be accepted.
+    ///
+    /// Other changes from this manifest are already applied to the current one.
+    #[serde(default)]
+    pub pending_manifest: Option<SiteManifest>,
+
+    /// A cached raw web app manifest that was downloaded last, if it is not applied.
+    ///
+    /// Contains the pending manifest. It is kept after the pending manifest is
+    /// rejected, so the same manifest is not offered again until it changes.
+    #[serde(default)]
+    pub latest_cache: Option<ManifestCache>,
+
+    /// Previous web app manifests, from the oldest to the newest.
+    ///
+    /// A manifest is added to the history when a manifest update
+    /// or a rollback replaces it, and only a few are kept.
+    #[serde(default)]
+    pub history: Vec<ManifestRevision>,
+
+    /// Whether the web app manifest is pinned.
+    ///
+    /// Manifests of pinned web apps are not updated, but their
+    /// system integration is still refreshed when updating them.
+    #[serde(default)]
+    pub pinned: bool,
+
+    /// Whether some icons failed to process when they were last updated.
+    ///
+    /// Such icons are updated again on the next update,
+    /// even if the manifest has not been modified.
+    #[serde(default)]
+    pub failed_icons: bool,
+
+    /// Usage statistics of the web app.
+    #[serde(default)]
+    pub usage: SiteUsage,
 }
 
 impl Site {
-    fn download(url: &Url, client: &Client) -> Result<String> {
+    /// Downloads the manifest and returns it with its validators.
+    ///
+    /// If the cache is provided, its validators are sent with the request, and
+    /// `None` is returned if the server responds that the manifest has not been
+    /// modified.
+    fn download(
+        url: &Url,
+        client: &Client,
+        cache: Option<&ManifestCache>,
+    ) -> Result<Option<ManifestCache>> {
+        let mut cache = match cache {
+            Some(cache) => cache.clone(),
+            None => ManifestCache::default(),
+        };
+
         // If the URL is not a data URL, just download it using reqwest
         let json = if url.scheme() != "data" {
-            client
-                .get(url.to_owned())
-                .header(reqwest::header::REFERER, url.to_string())
-                .send()?
-                .text()?
+            let mut request =
+                client.get(url.to_owned()).header(reqwest::header::REFERER, url.to_string());
+
+            if let Some(etag) = &cache.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cache.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+
+            let response = request.send()?;
+            if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+
+            // Error pages should not be parsed as the manifest
+            let response = response.error_for_status()?;
+
+            let header = |name| {
+                response.headers().get(name).and_then(|value| value.to_str().ok()).map(Into::into)
+            };
+            cache.etag = header(reqwest::header::ETAG);
+            cache.last_modified = header(reqwest::header::LAST_MODIFIED);
+
+            response.text()?
 
         // If the URL is a data URL (used for installing non-PWA sites), decode it using data-url
         } else {
@@ -131,66 +662,293 @@ impl Site {
         };
 
         // Trim BOM from the URL to prevent JSON parse errors
-        Ok(json.trim_start_matches('\u{feff}').into())
+        cache.body = Some(json.trim_start_matches('\u{feff}').into());
+        Ok(Some(cache))
     }
 
-    #[inline]
-    pub fn new(profile: Ulid, config: SiteConfig, client: &Client) -> Result<Self> {
+    /// Downloads, parses and processes the web app manifest.
+    pub fn fetch_manifest(
+        manifest_url: &Url,
+        document_url: &Url,
+        client: &Client,
+    ) -> Result<(SiteManifest, ManifestCache)> {
         info!("Downloading the web app manifest");
-        let json = Self::download(&config.manifest_url, client).context(DOWNLOAD_ERROR)?;
+        let cache = Self::download(manifest_url, client, None)
+            .context(DOWNLOAD_ERROR)?
+            .context(DOWNLOAD_ERROR)?;
+
+        info!("Parsing the web app manifest");
+        let json = cache.body.as_deref().unwrap_or_default();
+        let manifest = Self::parse_manifest(json, manifest_url, document_url)?;
+
+        Ok((manifest, cache))
+    }
 
+    /// Parses and processes the raw web app manifest.
+    fn parse_manifest(json: &str, manifest_url: &Url, document_url: &Url) -> Result<SiteManifest> {
         // If the manifest URL is a data URL, replace it with the document URL
-        let manifest_url = if config.manifest_url.scheme() != "data" {
-            &config.manifest_url
-        } else {
-            &config.document_url
-        };
+        let manifest_url =
+            if manifest_url.scheme() != "data" { manifest_url } else { document_url };
 
-        info!("Parsing the web app manifest");
-        let mut manifest: SiteManifest = serde_json::from_str(&json).context(PARSE_ERROR)?;
-        manifest.process(&config.document_url, manifest_url).context(PARSE_ERROR)?;
+        let mut manifest: SiteManifest = serde_json::from_str(json).context(PARSE_ERROR)?;
+        manifest.process(document_url, manifest_url).context(PARSE_ERROR)?;
+
+        Ok(manifest)
+    }
 
-        Ok(Self { ulid: Ulid::new(), profile, config, manifest })
+    #[inline]
+    pub fn new(profile: Ulid, config: SiteConfig, client: &Client) -> Result<Self> {
+        let (manifest, cache) =
+            Self::fetch_manifest(&config.manifest_url, &config.document_url, client)?;
+
+        Ok(Self {
+            ulid: Ulid::new(),
+            profile,
+            config,
+            manifest,
+            cache,
+            pending_manifest: None,
+            latest_cache: None,
+            history: vec![],
+            pinned: false,
+            failed_icons: false,
+            usage: SiteUsage::default(),
+        })
     }
 
+    /// Returns the time when the web app was last used, in milliseconds since the Unix epoch.
+    ///
+    /// Web apps that have not been launched yet were last used when they were installed.
     #[inline]
-    pub fn update(&mut self, client: &Client) -> Result<()> {
+    pub fn last_used(&self) -> u64 {
+        self.usage.last_launched.unwrap_or_else(|| self.ulid.timestamp_ms())
+    }
+
+    /// Updates the web app manifest and returns its changes.
+    ///
+    /// Uses a conditional request, so the manifest is only downloaded and
+    /// processed again if the server reports that it has been modified.
+    ///
+    /// Changes of sensitive fields (such as name, icons, scope and start URL) are
+    /// only applied if they are accepted. Otherwise, only other changes are applied,
+    /// and the new manifest is kept as pending until it is accepted or rejected.
+    /// See [`Site::apply_manifest`] for details.
+    ///
+    /// The replaced manifest is added to the manifest history. Manifests of
+    /// pinned web apps are not updated.
+    pub fn update(&mut self, client: &Client, accept: bool) -> Result<Vec<ManifestChange>> {
         // There is nothing to update if the manifest is a data URL because it is always static
         if self.config.manifest_url.scheme() == "data" {
-            return Ok(());
+            return Ok(vec![]);
         }
 
+        if self.pinned {
+            info!("Web app manifest is pinned, skipping manifest update");
+            return Ok(vec![]);
+        }
+
+        // The latest manifest may not be applied yet, but it does not need to be processed again
+        let latest = self.latest_cache.as_ref().unwrap_or(&self.cache);
+
         info!("Downloading the web app manifest");
-        let json = Self::download(&self.config.manifest_url, client).context(DOWNLOAD_ERROR)?;
+        let cache = match Self::download(&self.config.manifest_url, client, Some(latest))
+            .context(DOWNLOAD_ERROR)?
+        {
+            Some(cache) => cache,
+            None => {
+                info!("Web app manifest has not been modified");
+                return Ok(vec![]);
+            }
+        };
+
+        self.apply_manifest(cache, accept)
+    }
+
+    /// Applies the downloaded manifest and returns all its changes.
+    ///
+    /// Sensitive changes are only applied if they are accepted, and are otherwise
+    /// kept pending. The replaced manifest is added to the history. Does nothing
+    /// if the raw manifest is the same as the latest one.
+    pub fn apply_manifest(
+        &mut self,
+        cache: ManifestCache,
+        accept: bool,
+    ) -> Result<Vec<ManifestChange>> {
+        let latest = self.latest_cache.as_ref().unwrap_or(&self.cache);
+
+        // The same manifest may be downloaded again, such as from servers without validators
+        if cache.body == latest.body {
+            info!("Web app manifest has not been modified");
+            match &mut self.latest_cache {
+                Some(latest) => *latest = cache,
+                None => self.cache = cache,
+            }
+            return Ok(vec![]);
+        }
 
         info!("Parsing the web app manifest");
-        let mut manifest: SiteManifest = serde_json::from_str(&json).context(PARSE_ERROR)?;
-        manifest
-            .process(&self.config.document_url, &self.config.manifest_url)
-            .context(PARSE_ERROR)?;
+        let json = cache.body.as_deref().unwrap_or_default();
+        let manifest =
+            Self::parse_manifest(json, &self.config.manifest_url, &self.config.document_url)?;
+
+        let changes = ManifestChange::diff(
+            &self.manifest,
+            self.cache.body.as_deref(),
+            &manifest,
+            cache.body.as_deref(),
+        )?;
+
+        if accept || !changes.iter().any(|change| change.sensitive) {
+            self.archive_manifest();
+            self.manifest = manifest;
+            self.cache = cache;
+            self.pending_manifest = None;
+            self.latest_cache = None;
+            return Ok(changes);
+        }
+
+        // Apply other changes, but keep sensitive fields until the new manifest is accepted
+        // The raw manifest is also kept, as some sensitive fields are only read from it
+        let mut merged = to_object(&manifest)?;
+        let current = to_object(&self.manifest)?;
+        for field in SENSITIVE_FIELDS {
+            match current.get(field) {
+                Some(value) => merged.insert(field.into(), value.clone()),
+                None => merged.remove(field),
+            };
+        }
+
+        self.manifest = serde_json::from_value(Value::Object(merged)).context(PARSE_ERROR)?;
+        self.pending_manifest = Some(manifest);
+        self.latest_cache = Some(cache);
+        Ok(changes)
+    }
+
+    /// Returns changes of the pending manifest that are waiting to be accepted.
+    #[inline]
+    pub fn pending_changes(&self) -> Result<Vec<ManifestChange>> {
+        let latest = self.latest_cache.as_ref().and_then(|cache| cache.body.as_deref());
+        match &self.pending_manifest {
+            Some(pending) => {
+                ManifestChange::diff(&self.manifest, self.cache.body.as_deref(), pending, latest)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Rolls back the manifest to the revision from the manifest history and returns its changes.
+    ///
+    /// If no revision is specified, the newest one is used. The current manifest is added
+    /// to the history, pending changes are discarded, and the manifest is pinned, so the
+    /// next update does not immediately replace it again.
+    pub fn rollback_manifest(&mut self, revision: Option<Ulid>) -> Result<Vec<ManifestChange>> {
+        let index = match revision {
+            Some(id) => self.history.iter().position(|revision| revision.id == id),
+            None => self.history.len().checked_sub(1),
+        };
+        let index = index.context("Manifest revision does not exist")?;
+
+        let json = self.history[index].cache.body.as_deref().unwrap_or_default();
+        let manifest =
+            Self::parse_manifest(json, &self.config.manifest_url, &self.config.document_url)?;
+        let changes = ManifestChange::diff(
+            &self.manifest,
+            self.cache.body.as_deref(),
+            &manifest,
+            Some(json),
+        )?;
+
+        let revision = self.history.remove(index);
+        self.archive_manifest();
 
         self.manifest = manifest;
-        Ok(())
+        self.cache = revision.cache;
+        self.pending_manifest = None;
+        self.latest_cache = None;
+        self.pinned = true;
+
+        Ok(changes)
     }
 
+    /// Adds the current manifest to the manifest history and removes the oldest ones.
+    fn archive_manifest(&mut self) {
+        if self.cache.body.is_none() {
+            return;
+        }
+
+        let cache = self.cache.clone();
+        self.history.push(ManifestRevision { id: Ulid::new(), cache });
+
+        let excess = self.history.len().saturating_sub(MANIFEST_HISTORY_SIZE);
+        self.history.drain(..excess);
+    }
+
+    /// Applies the pending manifest and returns whether it existed.
+    ///
+    /// The current manifest is added to the history.
+    pub fn accept_manifest_changes(&mut self) -> bool {
+        let pending = match self.pending_manifest.take() {
+            Some(pending) => pending,
+            None => return false,
+        };
+
+        self.archive_manifest();
+        self.manifest = pending;
+        if let Some(cache) = self.latest_cache.take() {
+            self.cache = cache;
+        }
+
+        true
+    }
+
+    /// Discards the pending manifest and returns whether it existed.
+    ///
+    /// The manifest is only offered again once it changes on the server.
+    /// The latest raw manifest is kept, so it can be compared to the next one.
     #[inline]
+    pub fn reject_manifest_changes(&mut self) -> bool {
+        self.pending_manifest.take().is_some()
+    }
+
+    /// Launches the web app in its profile.
+    ///
+    /// Arguments and environment variables are merged in the following order,
+    /// where later ones take precedence:
+    ///
+    /// 1. Variables needed for the runtime features enabled in the config.
+    /// 2. Policy and global (or command-line) arguments and variables.
+    /// 3. Profile arguments and variables.
+    /// 4. Web app runtime feature overwrites, arguments and variables.
+    ///
+    /// Arguments are appended in that order, and variables with the same
+    /// name are overwritten by the later ones. A runtime feature that is
+    /// explicitly disabled for the web app has its variable set to `0`.
+    ///
+    /// When the launch is forwarded to a running instance of the profile,
+    /// `--no-remote` and `MOZ_NO_REMOTE` are cleared from all sources, as the
+    /// runtime would otherwise refuse to start with the profile already in use.
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
     pub fn launch<I: IntoIterator<Item = (String, String)>>(
         &self,
         dirs: &ProjectDirs,
         runtime: &Runtime,
         config: &Config,
+        profile: &Profile,
         url: &Option<Url>,
+        files: &[PathBuf],
+        forward: bool,
         arguments: &[String],
         variables: I,
     ) -> Result<Child> {
-        let profile = dirs.userdata.join("profiles").join(self.profile.to_string());
+        let directory = dirs.userdata.join("profiles").join(self.profile.to_string());
 
         // Pass all required PWA arguments to the runtime
         #[rustfmt::skip]
         let mut args = vec![
             "--class".into(), format!("FFPWA-{}", self.ulid.to_string()),
             "--name".into(), format!("FFPWA-{}", self.ulid.to_string()),
-            "--profile".into(), profile.display().to_string(),
+            "--profile".into(), directory.display().to_string(),
             "--pwa".into(), self.ulid.to_string(),
         ];
 
@@ -199,6 +957,17 @@ impl Site {
             args.extend_from_slice(&["--url".into(), url.to_string()]);
         }
 
+        // Let the runtime know how to handle launching when the web app is already opened
+        if let Some(mode) = self.client_mode() {
+            args.extend_from_slice(&["--pwa-client-mode".into(), mode.as_str().into()]);
+        }
+
+        // Hand files opened with a file handler to the runtime
+        // It currently only opens the handler action, as files cannot be passed to web apps yet
+        for file in files {
+            args.extend_from_slice(&["--file".into(), file.display().to_string()]);
+        }
+
         // Pass variables needed for specific runtime features
         let mut vars = BTreeMap::new();
         if config.runtime_enable_wayland {
@@ -211,59 +980,432 @@ impl Site {
             vars.insert("GTK_USE_PORTAL".into(), "1".into());
         }
 
-        // Include all user arguments and variables and launch the runtime
+        // Include all user arguments and variables
+        // Profile arguments and variables are included after the global ones
         args.extend_from_slice(arguments);
+        args.extend_from_slice(&profile.arguments);
         vars.extend(variables);
+        vars.extend(profile.variables.clone());
+
+        // Web app overwrites of runtime features are applied after user variables
+        let features = [
+            ("MOZ_ENABLE_WAYLAND", self.config.runtime_enable_wayland),
+            ("MOZ_USE_XINPUT2", self.config.runtime_use_xinput2),
+            ("GTK_USE_PORTAL", self.config.runtime_use_portals),
+        ];
+        for (name, enabled) in features {
+            if let Some(enabled) = enabled {
+                vars.insert(name.into(), if enabled { "1" } else { "0" }.into());
+            }
+        }
+
+        // Web app arguments and variables are included last and the runtime is launched
+        args.extend_from_slice(&self.config.arguments);
+        vars.extend(self.config.variables.clone());
+
+        // Forwarded launches need the Firefox remote mechanism to reach the running instance
+        // Firefox accepts flags with one or two dashes, or a slash on Windows
+        // It ignores empty variables, which also overwrites the one inherited from the environment
+        if forward {
+            args.retain(|arg| {
+                !arg.trim_start_matches(['-', '/']).eq_ignore_ascii_case("no-remote")
+            });
+            vars.insert("MOZ_NO_REMOTE".into(), "".into());
+        }
+
         runtime.run(&args, vars)
     }
 }
 
 impl Site {
     /// Start URL is used as an info URL on supported systems.
+    ///
+    /// Fails if the web app has a malformed manifest without
+    /// an absolute start URL and no custom start URL is set.
     #[rustfmt::skip]
-    pub fn url(&self) -> String {
+    pub fn url(&self) -> Result<String> {
         // Try to get user-specified start URL
-        if let Some(url) = &self.config.start_url { url.to_string() }
+        if let Some(url) = &self.config.start_url { Ok(url.to_string()) }
 
         // If not set, use manifest-provided start URL
-        else if let ManifestUrl::Absolute(url) = &self.manifest.start_url { url.to_string() }
+        else if let ManifestUrl::Absolute(url) = &self.manifest.start_url { Ok(url.to_string()) }
 
-        // This should not happen on valid web apps
-        else { unreachable!("{}", INVALID_URL) }
+        // This only happens on malformed web apps
+        else { bail!("{}: {}", INVALID_START_URL, self.ulid) }
     }
 
     /// Domain of a web app's scope is used as a publisher name
     /// on supported systems or when the app name is undefined.
-    pub fn domain(&self) -> String {
-        if let ManifestUrl::Absolute(url) = &self.manifest.scope {
-            match url.host() {
-                Some(domain) => domain.to_string(),
-                None => unreachable!("{}", INVALID_URL),
-            }
-        } else {
-            unreachable!("{}", INVALID_URL)
+    ///
+    /// Fails if the web app has a malformed manifest without an
+    /// absolute scope, or if the scope does not have a host.
+    pub fn domain(&self) -> Result<String> {
+        match &self.manifest.scope {
+            ManifestUrl::Absolute(url) => match url.host() {
+                Some(domain) => Ok(domain.to_string()),
+                None => bail!("{}: {}", INVALID_SCOPE, self.ulid),
+            },
+            _ => bail!("{}: {}", INVALID_SCOPE, self.ulid),
         }
     }
 
     /// First tries the user-specified name, then try manifest name
     /// and then short name. If no name is specified, uses the domain.
+    ///
+    /// If the locale is set, translations of the manifest name and short
+    /// name for that locale are tried before the default manifest ones.
+    ///
+    /// Malformed web apps without a domain fall back to the
+    /// host of the document URL, and then to the web app ID.
     pub fn name(&self) -> String {
+        let translation = self.translation().unwrap_or_default();
+
         self.config
             .name
             .as_ref()
             .cloned()
+            .or(translation.name)
+            .or(translation.short_name)
             .or_else(|| self.manifest.name.as_ref().cloned())
             .or_else(|| self.manifest.short_name.as_ref().cloned())
-            .unwrap_or_else(|| self.domain())
+            .or_else(|| self.domain().ok())
+            .or_else(|| self.config.document_url.host_str().map(Into::into))
+            .unwrap_or_else(|| format!("Site {}", self.ulid))
+    }
+
+    /// Returns the web app identity, as defined by the manifest specification.
+    ///
+    /// The identity is the manifest `id` resolved against the origin of the start
+    /// URL, or the start URL itself if the `id` is missing or has another origin.
+    /// The `id` is read from the cached raw manifest, so web apps installed before
+    /// the manifest was cached always use the start URL. Fragments are ignored.
+    pub fn identity(&self) -> Option<Url> {
+        let start_url = match &self.manifest.start_url {
+            ManifestUrl::Absolute(url) => url,
+            _ => return None,
+        };
+
+        let id =
+            self.raw_manifest().and_then(|manifest| manifest.get("id")?.as_str().map(String::from));
+
+        let origin = Url::parse(&start_url.origin().ascii_serialization()).ok();
+        let id = match (origin, id) {
+            (Some(origin), Some(id)) => origin.join(&id).ok(),
+            _ => None,
+        };
+
+        let mut identity = match id {
+            Some(id) if id.origin() == start_url.origin() => id,
+            _ => start_url.clone(),
+        };
+
+        identity.set_fragment(None);
+        Some(identity)
+    }
+
+    /// Finds a web app with the same identity in the profile and returns its ID.
+    ///
+    /// Web apps with the same identity in the same profile are duplicates. If the
+    /// identity of any of them is not known, because its raw manifest is not cached,
+    /// web apps with the same manifest URL or start URL are also duplicates.
+    pub fn find_duplicate<'a, I>(&self, sites: I, profile: &Ulid) -> Option<Ulid>
+    where
+        I: IntoIterator<Item = &'a Site>,
+    {
+        let identity = self.identity()?;
+
+        // Web apps without the cached raw manifest do not know their `id`
+        // They are also duplicates if they use the same manifest URL or start URL
+        let similar = |existing: &Site| {
+            (self.cache.body.is_none() || existing.cache.body.is_none())
+                && (existing.config.manifest_url == self.config.manifest_url
+                    || existing.manifest.start_url == self.manifest.start_url)
+        };
+
+        sites
+            .into_iter()
+            .find(|existing| {
+                existing.ulid != self.ulid
+                    && existing.profile == *profile
+                    && (existing.identity().as_ref() == Some(&identity) || similar(existing))
+            })
+            .map(|existing| existing.ulid)
+    }
+
+    /// Returns file handlers declared by the web app manifest.
+    ///
+    /// Handlers are read from the cached raw manifest, as they are not part of the
+    /// processed manifest. Actions are resolved against the manifest URL, and handlers
+    /// with an action outside the scope or without any valid file extension are ignored.
+    pub fn file_handlers(&self) -> Vec<FileHandler> {
+        let handlers =
+            match self.raw_manifest().and_then(|mut manifest| manifest.remove("file_handlers")) {
+                Some(Value::Array(handlers)) => handlers,
+                _ => return vec![],
+            };
+
+        let mut result = vec![];
+        for handler in handlers {
+            let action = match handler.get("action").and_then(Value::as_str) {
+                Some(action) => match self.resolve_within_scope(action) {
+                    Some(action) => action,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            // Extensions may be specified as a single string or a list of strings
+            let mut accept = BTreeMap::new();
+            for (mime, extensions) in
+                handler.get("accept").and_then(Value::as_object).into_iter().flatten()
+            {
+                let extensions: Vec<String> = match extensions {
+                    Value::String(extension) => vec![extension.clone()],
+                    Value::Array(extensions) => {
+                        extensions.iter().filter_map(Value::as_str).map(String::from).collect()
+                    }
+                    _ => continue,
+                };
+                let extensions: Vec<String> =
+                    extensions.into_iter().filter(|extension| valid_extension(extension)).collect();
+
+                if valid_mime_type(mime) && !extensions.is_empty() {
+                    accept.insert(mime.clone(), extensions);
+                }
+            }
+
+            if accept.is_empty() {
+                continue;
+            }
+
+            let name = handler.get("name").and_then(Value::as_str).map(String::from);
+            result.push(FileHandler { action, name, accept });
+        }
+
+        result
+    }
+
+    /// Returns translations of the name, description and keywords by their locales.
+    ///
+    /// Translations are read from the `*_localized` members of the cached raw manifest,
+    /// as they are not part of the processed manifest. Each translation is either a
+    /// string (or a list of strings for keywords), or an object with a `value` field.
+    pub fn translations(&self) -> BTreeMap<String, SiteTranslation> {
+        let manifest = match self.raw_manifest() {
+            Some(manifest) => manifest,
+            None => return BTreeMap::new(),
+        };
+
+        // Returns all translations of the member, unwrapping values from objects
+        let localized = |member: &str| -> Vec<(String, Value)> {
+            let translations = match manifest.get(&format!("{member}_localized")) {
+                Some(Value::Object(translations)) => translations,
+                _ => return vec![],
+            };
+
+            translations
+                .iter()
+                .filter(|(locale, _)| !locale.is_empty())
+                .map(|(locale, value)| match value {
+                    Value::Object(value) => (locale.clone(), value.get("value").cloned()),
+                    value => (locale.clone(), Some(value.clone())),
+                })
+                .filter_map(|(locale, value)| Some((locale, value?)))
+                .collect()
+        };
+
+        let mut translations: BTreeMap<String, SiteTranslation> = BTreeMap::new();
+        for (locale, value) in localized("name") {
+            translations.entry(locale).or_default().name = value.as_str().map(String::from);
+        }
+        for (locale, value) in localized("short_name") {
+            translations.entry(locale).or_default().short_name = value.as_str().map(String::from);
+        }
+        for (locale, value) in localized("description") {
+            translations.entry(locale).or_default().description = value.as_str().map(String::from);
+        }
+        for (locale, value) in localized("keywords") {
+            if let Value::Array(keywords) = value {
+                let keywords = keywords.iter().filter_map(Value::as_str).map(String::from);
+                translations.entry(locale).or_default().keywords = Some(keywords.collect());
+            }
+        }
+
+        translations
+    }
+
+    /// Returns the translation for the configured locale.
+    ///
+    /// Locales are matched case-insensitively. If there is no translation for the
+    /// exact locale, such as `pt-BR`, the translation for its language is used.
+    fn translation(&self) -> Option<SiteTranslation> {
+        let locale = self.config.locale.as_deref()?;
+        let language = locale.split(['-', '_']).next()?;
+        let mut translations = self.translations();
+
+        let key = translations
+            .keys()
+            .find(|key| key.replace('_', "-").eq_ignore_ascii_case(&locale.replace('_', "-")))
+            .or_else(|| translations.keys().find(|key| key.eq_ignore_ascii_case(language)))
+            .cloned()?;
+
+        translations.remove(&key)
+    }
+
+    /// Returns the client mode declared by the `launch_handler` manifest member.
+    ///
+    /// The client mode is either a single mode or a list of modes, in which
+    /// case the first supported one is used. Returns `None` for the `auto`
+    /// mode or if the web app does not declare a supported mode.
+    pub fn client_mode(&self) -> Option<ClientMode> {
+        let handler = self.raw_manifest()?.remove("launch_handler")?;
+        let modes = match handler.get("client_mode")? {
+            Value::String(mode) => vec![mode.as_str()],
+            Value::Array(modes) => modes.iter().filter_map(Value::as_str).collect(),
+            _ => return None,
+        };
+
+        modes.into_iter().find_map(|mode| match mode {
+            "focus-existing" => Some(Some(ClientMode::FocusExisting)),
+            "navigate-existing" => Some(Some(ClientMode::NavigateExisting)),
+            "navigate-new" => Some(Some(ClientMode::NavigateNew)),
+            "auto" => Some(None),
+            _ => None,
+        })?
+    }
+
+    /// Returns the share target declared by the web app manifest.
+    ///
+    /// The share target is read from the cached raw manifest, as it is not part of
+    /// the processed manifest. It is ignored if its action is outside the scope,
+    /// or if its method or encoding type is not supported by the specification.
+    pub fn share_target(&self) -> Option<ShareTarget> {
+        let target = self.raw_manifest()?.remove("share_target")?;
+        let action = self.resolve_within_scope(target.get("action")?.as_str()?)?;
+
+        let method = match target.get("method").and_then(Value::as_str) {
+            Some(method) if method.eq_ignore_ascii_case("POST") => ShareMethod::Post,
+            Some(method) if method.eq_ignore_ascii_case("GET") => ShareMethod::Get,
+            Some(_) => return None,
+            None => ShareMethod::Get,
+        };
+
+        let enctype = target.get("enctype").and_then(Value::as_str).map(str::to_lowercase);
+        let enctype = enctype.unwrap_or_else(|| SHARE_URLENCODED.into());
+        if enctype != SHARE_URLENCODED && (method == ShareMethod::Get || enctype != SHARE_MULTIPART)
+        {
+            return None;
+        }
+
+        let param = |name: &str| {
+            let value = target.get("params")?.get(name)?.as_str()?;
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.to_owned())
+            }
+        };
+        let params = ShareData { title: param("title"), text: param("text"), url: param("url") };
+
+        Some(ShareTarget { action, method, enctype, params })
+    }
+
+    /// Returns the first enabled file handler that accepts all files.
+    pub fn file_handler(&self, files: &[impl AsRef<Path>]) -> Option<FileHandler> {
+        self.file_handlers().into_iter().find(|handler| {
+            self.config.enabled_file_handlers.contains(&handler.action.to_string())
+                && files.iter().all(|file| handler.accepts(file.as_ref()))
+        })
+    }
+
+    /// Parses the cached raw manifest.
+    ///
+    /// Used for manifest members that are not part of the processed manifest.
+    fn raw_manifest(&self) -> Option<Map<String, Value>> {
+        match serde_json::from_str(self.cache.body.as_deref()?) {
+            Ok(Value::Object(manifest)) => Some(manifest),
+            _ => None,
+        }
+    }
+
+    /// Resolves the manifest URL member and checks that it is within the scope.
+    fn resolve_within_scope(&self, url: &str) -> Option<Url> {
+        // If the manifest URL is a data URL, members are resolved against the document URL
+        let base = if self.config.manifest_url.scheme() != "data" {
+            &self.config.manifest_url
+        } else {
+            &self.config.document_url
+        };
+
+        let scope = match &self.manifest.scope {
+            ManifestUrl::Absolute(scope) => scope,
+            _ => return None,
+        };
+
+        let url = base.join(url).ok()?;
+        if !within_scope(&url, scope) {
+            return None;
+        }
+
+        Some(url)
+    }
+
+    /// Checks whether the URL may be opened in the web app.
+    ///
+    /// The URL needs to be within the web app scope or within
+    /// one of the scopes of the enabled URL handlers.
+    pub fn allows_url(&self, url: &Url) -> bool {
+        if let ManifestUrl::Absolute(scope) = &self.manifest.scope {
+            if within_scope(url, scope) {
+                return true;
+            }
+        }
+
+        self.config
+            .enabled_url_handlers
+            .iter()
+            .filter_map(|handler| Url::parse(handler).ok())
+            .any(|scope| within_scope(url, &scope))
+    }
+
+    /// Checks that the web app has a valid start URL and scope.
+    ///
+    /// Some system integrations need them, so malformed web apps
+    /// are skipped by operations on all web apps instead of failing
+    /// them. They can still be launched, updated and uninstalled.
+    #[inline]
+    pub fn validate(&self) -> Result<()> {
+        self.url()?;
+        self.domain()?;
+        Ok(())
+    }
+
+    /// Downloads the manifest again and replaces the current one if it is valid.
+    ///
+    /// Used to repair malformed web apps. Pending manifest changes are discarded.
+    pub fn repair(&mut self, client: &Client) -> Result<()> {
+        let (manifest, cache) =
+            Self::fetch_manifest(&self.config.manifest_url, &self.config.document_url, client)?;
+
+        let repaired =
+            Self { manifest, cache, pending_manifest: None, latest_cache: None, ..self.clone() };
+        repaired.validate().context("Downloaded web app manifest is still malformed")?;
+
+        *self = repaired;
+        Ok(())
     }
 
     /// First tries the user-specified description, then try manifest description.
     /// If no description is specified, returns an empty string.
+    ///
+    /// If the locale is set, the translation of the manifest description
+    /// for that locale is tried before the default manifest one.
     pub fn description(&self) -> String {
         self.config
             .description
             .as_ref()
             .cloned()
+            .or_else(|| self.translation()?.description)
             .or_else(|| self.manifest.description.as_ref().cloned())
             .unwrap_or_else(|| "".into())
     }
@@ -1,19 +1,38 @@
+use std::collections::BTreeMap;
+
 use anyhow::{bail, Context, Result};
 use cfg_if::cfg_if;
-use log::info;
+use log::{info, warn};
+use reqwest::blocking::Client;
 
+use crate::components::bundle::Bundle;
+use crate::components::discovery::Discovery;
 use crate::components::runtime::Runtime;
+use crate::components::site::{ManifestChange, Site};
 use crate::connector::request::{
+    CheckConsistency,
+    CheckSite,
+    CloneSite,
     CreateProfile,
+    DiscoverSite,
+    ExportSites,
+    GetBackupList,
     GetConfig,
+    GetManifestChanges,
     GetProfileList,
     GetSiteList,
     GetSystemVersions,
+    ImportSites,
     InstallRuntime,
     InstallSite,
     LaunchSite,
+    MoveSite,
+    PinManifest,
     RegisterProtocolHandler,
     RemoveProfile,
+    ResolveManifestChanges,
+    RestoreBackup,
+    RollbackManifest,
     SetConfig,
     UninstallRuntime,
     UninstallSite,
@@ -25,20 +44,30 @@ use crate::connector::request::{
 use crate::connector::response::ConnectorResponse;
 use crate::connector::Connection;
 use crate::console::app::{
+    ConfigRestoreCommand,
+    DoctorCommand,
     ProfileCreateCommand,
     ProfileRemoveCommand,
     ProfileUpdateCommand,
     RuntimeInstallCommand,
     RuntimeUninstallCommand,
+    SiteCheckCommand,
+    SiteCloneCommand,
     SiteInstallCommand,
     SiteLaunchCommand,
+    SiteManifestPinCommand,
+    SiteManifestRollbackCommand,
+    SiteManifestUnpinCommand,
+    SiteMoveCommand,
     SiteUninstallCommand,
     SiteUpdateCommand,
 };
+use crate::console::site::preinstall_policy_sites;
 use crate::console::Run;
 use crate::integrations;
 use crate::integrations::IntegrationInstallArgs;
-use crate::storage::Storage;
+use crate::storage::policy::Policy;
+use crate::storage::{backups, Storage};
 use crate::utils::construct_certificates_and_client;
 
 pub trait Process {
@@ -74,6 +103,7 @@ impl Process for GetConfig {
 impl Process for SetConfig {
     fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
         let mut storage = Storage::load(connection.dirs)?;
+        storage.policy.check_config(&storage.config, &self.0)?;
         storage.config = self.0.to_owned();
         storage.write(connection.dirs)?;
         Ok(ConnectorResponse::ConfigSet)
@@ -108,8 +138,8 @@ impl Process for GetSiteList {
 impl Process for LaunchSite {
     fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
         cfg_if! {
-            if #[cfg(target_os = "macos")] { let command = SiteLaunchCommand { id: self.id, url: self.url.to_owned(), protocol: None, arguments: vec![], direct_launch: false }; }
-            else { let command = SiteLaunchCommand { id: self.id, url: self.url.to_owned(), protocol: None, arguments: vec![] }; }
+            if #[cfg(target_os = "macos")] { let command = SiteLaunchCommand { id: self.id, url: self.url.to_owned(), protocol: None, file: vec![], share: vec![], arguments: vec![], direct_launch: false }; }
+            else { let command = SiteLaunchCommand { id: self.id, url: self.url.to_owned(), protocol: None, file: vec![], share: vec![], arguments: vec![] }; }
         };
         command.run()?;
 
@@ -118,10 +148,12 @@ impl Process for LaunchSite {
 }
 
 impl Process for InstallSite {
-    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+    fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
         let command = SiteInstallCommand {
-            manifest_url: self.manifest_url.to_owned(),
+            manifest_url: Some(self.manifest_url.to_owned()),
             document_url: self.document_url.to_owned(),
+            page: None,
+            synthesize_manifest: false,
             start_url: self.start_url.to_owned(),
             icon_url: self.icon_url.to_owned(),
             profile: self.profile.to_owned(),
@@ -131,9 +163,15 @@ impl Process for InstallSite {
             keywords: self.keywords.to_owned(),
             launch_on_login: Some(self.launch_on_login),
             launch_on_browser: Some(self.launch_on_browser),
+            duplicate: self.duplicate.into(),
             system_integration: true,
             client: self.client.to_owned().into(),
         };
+
+        if let Err(error) = preinstall_policy_sites(connection.dirs, &command.client) {
+            warn!("Failed to preinstall web apps from the policy: {:#}", error);
+        }
+
         let ulid = command._run()?;
 
         Ok(ConnectorResponse::SiteInstalled(ulid))
@@ -161,53 +199,299 @@ impl Process for UpdateSite {
             description: self.description.to_owned(),
             categories: self.categories.clone().map(|x| x.unwrap_or_else(|| vec!["".into()])),
             keywords: self.keywords.clone().map(|x| x.unwrap_or_else(|| vec!["".into()])),
+            locale: self.locale.to_owned(),
             enabled_url_handlers: self.enabled_url_handlers.to_owned(),
             enabled_protocol_handlers: self.enabled_protocol_handlers.to_owned(),
+            enabled_file_handlers: self.enabled_file_handlers.to_owned(),
             launch_on_login: self.launch_on_login,
             launch_on_browser: self.launch_on_browser,
+            arguments: self.arguments.to_owned(),
+            variables: self.variables.as_ref().map(|variables| {
+                variables.iter().map(|(name, value)| format!("{}={}", name, value)).collect()
+            }),
+            runtime_enable_wayland: self.runtime_enable_wayland,
+            runtime_use_xinput2: self.runtime_use_xinput2,
+            runtime_use_portals: self.runtime_use_portals,
             update_manifest: self.update_manifest,
             update_icons: self.update_icons,
+            accept_manifest_changes: self.accept_manifest_changes,
+            reject_manifest_changes: false,
             system_integration: true,
             client: self.client.to_owned().into(),
         };
+        let changes = command._run()?;
+
+        Ok(ConnectorResponse::SiteUpdated(changes))
+    }
+}
+
+impl Process for MoveSite {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command =
+            SiteMoveCommand { id: self.id, profile: self.profile, system_integration: true };
         command.run()?;
 
-        Ok(ConnectorResponse::SiteUpdated)
+        Ok(ConnectorResponse::SiteMoved)
+    }
+}
+
+impl Process for CloneSite {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command = SiteCloneCommand {
+            id: self.id,
+            profile: self.profile,
+            system_integration: true,
+            client: self.client.to_owned().into(),
+        };
+        let ulid = command._run()?;
+
+        Ok(ConnectorResponse::SiteCloned(ulid))
     }
 }
 
 impl Process for UpdateAllSites {
     fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
-        let mut storage = Storage::load(connection.dirs)?;
+        if let Err(error) = preinstall_policy_sites(connection.dirs, &self.client.to_owned().into())
+        {
+            warn!("Failed to preinstall web apps from the policy: {:#}", error);
+        }
+
+        let snapshot = Storage::load(connection.dirs)?.unlock();
+        let mut changes = BTreeMap::new();
+        let mut updated = vec![];
+        let mut failed = vec![];
 
-        for site in storage.sites.values_mut() {
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        // Web apps that fail to update should not prevent updating other web apps
+        for mut site in snapshot.sites.values().cloned() {
             info!("Updating web app {}", site.ulid);
-            let old_name = site.name();
+            match self.update_site(connection, &client, &snapshot.policy, &mut site) {
+                Ok(site_changes) => {
+                    if !site_changes.is_empty() {
+                        changes.insert(site.ulid, site_changes);
+                    }
+                    updated.push(site);
+                }
+                Err(error) => {
+                    warn!("Failed to update web app {}: {:#}", site.ulid, error);
+                    failed.push(format!("{}: {:#}", site.ulid, error));
+                }
+            }
+        }
+
+        let mut storage = Storage::load(connection.dirs)?;
+        for site in updated {
+            // Web apps removed in the meantime should stay removed
+            if storage.sites.contains_key(&site.ulid) {
+                storage.merge_site(site)?;
+            }
+        }
+        storage.write(connection.dirs)?;
+
+        if !failed.is_empty() {
+            bail!("Failed to update some web apps: {}", failed.join("; "));
+        }
+
+        Ok(ConnectorResponse::AllSitesUpdated(changes))
+    }
+}
+
+impl UpdateAllSites {
+    /// Updates the manifest and system integration of a single web app.
+    ///
+    /// Web apps that are not allowed by the policy or are malformed are skipped
+    /// with a warning. Returns the applied and pending manifest changes.
+    fn update_site(
+        &self,
+        connection: &Connection,
+        client: &Client,
+        policy: &Policy,
+        site: &mut Site,
+    ) -> Result<Vec<ManifestChange>> {
+        let old_name = site.name();
+        let old_manifest = site.manifest.clone();
+        let mut changes = vec![];
+
+        // Sensitive changes are kept pending, so they can be reviewed separately
+        if self.update_manifest {
+            let original = site.clone();
+            changes = site.update(client, false).context("Failed to update web app manifest")?;
+
+            if let Err(error) = policy.check_site(&site.config, &site.manifest) {
+                warn!("Skipping update of web app {}: {:#}", site.ulid, error);
+                *site = original;
+                return Ok(vec![]);
+            }
+        }
+
+        if let Err(error) = site.validate() {
+            warn!("Skipping system integration of malformed web app: {:#}", error);
+            return Ok(changes);
+        }
+
+        // Icons only need to be regenerated if the manifest has changed or they have failed before
+        let changed = !self.update_manifest || site.manifest != old_manifest || site.failed_icons;
+
+        integrations::install(&IntegrationInstallArgs {
+            site,
+            dirs: connection.dirs,
+            client: Some(client),
+            update_manifest: self.update_manifest,
+            update_icons: self.update_icons && changed,
+            old_name: Some(&old_name),
+        })
+        .context("Failed to update system integration")?;
+
+        if self.update_icons && changed {
+            site.failed_icons = integrations::take_icon_failures();
+        }
+
+        Ok(changes)
+    }
+}
+
+impl Process for CheckSite {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command = SiteCheckCommand {
+            id: self.id,
+            manifest_url: self.manifest_url.to_owned(),
+            document_url: self.document_url.to_owned(),
+            client: self.client.to_owned().into(),
+        };
+
+        Ok(ConnectorResponse::SiteChecked(command._run()?))
+    }
+}
 
-            let client = construct_certificates_and_client(
+impl Process for GetManifestChanges {
+    fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
+        let storage = Storage::load_site(connection.dirs, &self.id)?.unlock();
+        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+        Ok(ConnectorResponse::ManifestChanges(site.pending_changes()?))
+    }
+}
+
+impl Process for ResolveManifestChanges {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        // Accepted changes are applied without downloading the manifest again
+        let command = SiteUpdateCommand {
+            id: self.id,
+            start_url: None,
+            icon_url: None,
+            name: None,
+            description: None,
+            categories: None,
+            keywords: None,
+            locale: None,
+            enabled_url_handlers: None,
+            enabled_protocol_handlers: None,
+            enabled_file_handlers: None,
+            launch_on_login: None,
+            launch_on_browser: None,
+            arguments: None,
+            variables: None,
+            runtime_enable_wayland: None,
+            runtime_use_xinput2: None,
+            runtime_use_portals: None,
+            update_manifest: false,
+            update_icons: self.accept,
+            accept_manifest_changes: self.accept,
+            reject_manifest_changes: !self.accept,
+            system_integration: true,
+            client: self.client.to_owned().into(),
+        };
+        command.run()?;
+
+        Ok(ConnectorResponse::ManifestChangesResolved)
+    }
+}
+
+impl Process for PinManifest {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        if self.pinned {
+            SiteManifestPinCommand { id: self.id }.run()?;
+        } else {
+            SiteManifestUnpinCommand { id: self.id }.run()?;
+        }
+
+        Ok(ConnectorResponse::ManifestPinned)
+    }
+}
+
+impl Process for RollbackManifest {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command = SiteManifestRollbackCommand {
+            id: self.id,
+            revision: self.revision,
+            system_integration: true,
+            client: self.client.to_owned().into(),
+        };
+        command.run()?;
+
+        Ok(ConnectorResponse::ManifestRolledBack)
+    }
+}
+
+impl Process for ExportSites {
+    fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
+        let storage = Storage::load(connection.dirs)?.unlock();
+
+        let client = if self.icons {
+            Some(construct_certificates_and_client(
                 &self.client.tls_root_certificates_der,
                 &self.client.tls_root_certificates_pem,
                 self.client.tls_danger_accept_invalid_certs,
                 self.client.tls_danger_accept_invalid_hostnames,
-            )?;
+            )?)
+        } else {
+            None
+        };
 
-            if self.update_manifest {
-                site.update(&client).context("Failed to update web app manifest")?;
-            }
+        let bundle = Bundle::new(&storage, &self.sites, client.as_ref())?;
+        Ok(ConnectorResponse::SitesExported(bundle))
+    }
+}
 
-            integrations::install(&IntegrationInstallArgs {
-                site,
-                dirs: connection.dirs,
-                client: Some(&client),
-                update_manifest: self.update_manifest,
-                update_icons: self.update_icons,
-                old_name: Some(&old_name),
-            })
-            .context("Failed to update system integration")?;
-        }
+impl Process for ImportSites {
+    fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
+        let storage = Storage::load(connection.dirs)?.unlock();
+
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        let template = self.template.as_deref();
+        let imported =
+            self.bundle.to_owned().import(&storage, connection.dirs, &client, true, template)?;
 
+        let mut storage = Storage::load(connection.dirs)?;
+        let ulids = imported.insert_into(&mut storage)?;
         storage.write(connection.dirs)?;
-        Ok(ConnectorResponse::AllSitesUpdated)
+
+        Ok(ConnectorResponse::SitesImported(ulids))
+    }
+}
+
+impl Process for DiscoverSite {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        let discovery = Discovery::new(&self.page, &client, self.synthesize_manifest)?;
+        Ok(ConnectorResponse::SiteDiscovered(discovery))
     }
 }
 
@@ -246,6 +530,10 @@ impl Process for UpdateProfile {
             id: self.id,
             name: self.name.to_owned(),
             description: self.description.to_owned(),
+            arguments: self.arguments.to_owned(),
+            variables: self.variables.as_ref().map(|variables| {
+                variables.iter().map(|(name, value)| format!("{}={}", name, value)).collect()
+            }),
         };
         command.run()?;
 
@@ -313,3 +601,27 @@ impl Process for UnregisterProtocolHandler {
         Ok(ConnectorResponse::ProtocolHandlerUnregistered)
     }
 }
+
+impl Process for GetBackupList {
+    fn process(&self, connection: &Connection) -> Result<ConnectorResponse> {
+        Ok(ConnectorResponse::BackupList(backups::list(connection.dirs)?))
+    }
+}
+
+impl Process for RestoreBackup {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command = ConfigRestoreCommand { id: self.id, quiet: true };
+        command.run()?;
+
+        Ok(ConnectorResponse::BackupRestored)
+    }
+}
+
+impl Process for CheckConsistency {
+    fn process(&self, _connection: &Connection) -> Result<ConnectorResponse> {
+        let command = DoctorCommand { fix: self.fix, client: self.client.to_owned().into() };
+        let problems = command._run()?;
+
+        Ok(ConnectorResponse::ConsistencyChecked(problems))
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 use anyhow::Result;
@@ -6,6 +7,7 @@ use ulid::Ulid;
 use url::Url;
 use web_app_manifest::resources::ProtocolHandlerResource;
 
+use crate::components::bundle::Bundle;
 use crate::connector::response::ConnectorResponse;
 use crate::storage::Config;
 
@@ -16,7 +18,7 @@ macro_rules! build_request_enum {
         use crate::connector::process::Process;
 
         /// TODO: Docs
-        #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+        #[derive(Deserialize, Debug, PartialEq, Clone)]
         #[serde(tag = "cmd", content = "params")]
         pub enum ConnectorRequest {
             $(
@@ -170,6 +172,9 @@ pub struct UninstallRuntime;
 
 /// Gets all installed web apps.
 ///
+/// Web apps include their usage statistics, so they
+/// can be ordered by when they were last launched.
+///
 /// # Parameters
 ///
 /// None.
@@ -273,6 +278,14 @@ pub struct InstallSite {
     #[serde(default)]
     pub launch_on_browser: bool,
 
+    /// What to do if the same web app is already installed in the profile (default: `allow`).
+    ///
+    /// Web apps are matched by their manifest ID, or start URL if it is not set.
+    /// Defaults to installing the web app again, as older extension versions
+    /// do not send this parameter and cannot handle the refusal.
+    #[serde(default)]
+    pub duplicate: DuplicateMode,
+
     /// Contains a HTTP client configuration.
     #[serde(default)]
     pub client: HTTPClientConfig,
@@ -306,7 +319,7 @@ pub struct UninstallSite {
 ///
 /// # Returns
 ///
-/// [`ConnectorResponse::SiteUpdated`] - No data.
+/// [`ConnectorResponse::SiteUpdated`] - List of manifest changes, including pending ones.
 ///
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct UpdateSite {
@@ -349,6 +362,12 @@ pub struct UpdateSite {
     #[serde(default, deserialize_with = "double_option")]
     pub keywords: Option<Option<Vec<String>>>,
 
+    /// A locale for the web app name and description.
+    ///
+    /// Set to `null` to use the default manifest values.
+    #[serde(default, deserialize_with = "double_option")]
+    pub locale: Option<Option<String>>,
+
     /// Enabled URL handlers.
     ///
     /// A list of enabled web app URL scopes that the browser
@@ -362,6 +381,12 @@ pub struct UpdateSite {
     /// If empty, no handlers are registered to the operating system.
     pub enabled_protocol_handlers: Option<Vec<String>>,
 
+    /// Enabled file handlers.
+    ///
+    /// A list of action URLs of enabled file handlers supported by this web app.
+    /// If empty, no file types are registered to the operating system.
+    pub enabled_file_handlers: Option<Vec<String>>,
+
     /// Whether the web app should be launched on the system login.
     #[serde(default)]
     pub launch_on_login: Option<bool>,
@@ -370,6 +395,36 @@ pub struct UpdateSite {
     #[serde(default)]
     pub launch_on_browser: Option<bool>,
 
+    /// Arguments passed to the runtime for this web app.
+    ///
+    /// Set to an empty list to remove all arguments.
+    #[serde(default)]
+    pub arguments: Option<Vec<String>>,
+
+    /// Environment variables passed to the runtime for this web app.
+    ///
+    /// Set to an empty map to remove all variables.
+    #[serde(default)]
+    pub variables: Option<BTreeMap<String, String>>,
+
+    /// Overwrite of the Wayland Display Server option.
+    ///
+    /// Set to `None` to use the config value.
+    #[serde(default, deserialize_with = "double_option")]
+    pub runtime_enable_wayland: Option<Option<bool>>,
+
+    /// Overwrite of the XInput2 option.
+    ///
+    /// Set to `None` to use the config value.
+    #[serde(default, deserialize_with = "double_option")]
+    pub runtime_use_xinput2: Option<Option<bool>>,
+
+    /// Overwrite of the XDG Desktop Portals option.
+    ///
+    /// Set to `None` to use the config value.
+    #[serde(default, deserialize_with = "double_option")]
+    pub runtime_use_portals: Option<Option<bool>>,
+
     /// Whether the manifest should be updated (default: `true`).
     #[serde(default = "default_as_true")]
     pub update_manifest: bool,
@@ -378,6 +433,65 @@ pub struct UpdateSite {
     #[serde(default = "default_as_true")]
     pub update_icons: bool,
 
+    /// Whether changes of sensitive manifest fields should be accepted (default: `false`).
+    ///
+    /// Sensitive fields are the name, short name, icons, scope, start URL, ID, file
+    /// handlers, share target, launch handler and translated (`*_localized`) members.
+    /// If not accepted, their changes are kept pending and can be reviewed
+    /// with [`GetManifestChanges`] and [`ResolveManifestChanges`].
+    #[serde(default)]
+    pub accept_manifest_changes: bool,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Moves a web app to another profile.
+///
+/// The web app keeps its ID, so existing launchers
+/// and shortcuts keep working. Its system integration
+/// is refreshed.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SiteMoved`] - No data.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct MoveSite {
+    /// A web app ID.
+    pub id: Ulid,
+
+    /// A profile ID where the web app should be moved.
+    pub profile: Ulid,
+}
+
+/// Clones a web app into another profile.
+///
+/// The clone gets a new ID and the same config, manifest
+/// and manifest history as the web app. It also gets its
+/// own system integration.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SiteCloned`] - ID of the cloned web app.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct CloneSite {
+    /// A web app ID.
+    pub id: Ulid,
+
+    /// A profile ID where the web app should be cloned.
+    pub profile: Ulid,
+
     /// Contains a HTTP client configuration.
     #[serde(default)]
     pub client: HTTPClientConfig,
@@ -391,7 +505,7 @@ pub struct UpdateSite {
 ///
 /// # Returns
 ///
-/// [`ConnectorResponse::AllSitesUpdated`] - No data.
+/// [`ConnectorResponse::AllSitesUpdated`] - Manifest changes of each changed web app.
 ///
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct UpdateAllSites {
@@ -488,6 +602,233 @@ pub struct UpdateProfile {
     /// A profile description.
     #[serde(default, deserialize_with = "double_option")]
     pub description: Option<Option<String>>,
+
+    /// Arguments passed to the runtime for this profile.
+    ///
+    /// Set to an empty list to remove all arguments.
+    #[serde(default)]
+    pub arguments: Option<Vec<String>>,
+
+    /// Environment variables passed to the runtime for this profile.
+    ///
+    /// Set to an empty map to remove all variables.
+    #[serde(default)]
+    pub variables: Option<BTreeMap<String, String>>,
+}
+
+/// Checks the manifest of an installed web app or a manifest URL for problems.
+///
+/// Reports missing names, invalid start URLs and scopes, start URLs outside
+/// the scope, broken or missing icons, and invalid protocol handlers.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SiteChecked`] - List of found problems.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct CheckSite {
+    /// A web app ID.
+    ///
+    /// Required if the manifest URL is not specified.
+    pub id: Option<Ulid>,
+
+    /// Direct URL of a web app manifest to check instead of an installed web app.
+    pub manifest_url: Option<Url>,
+
+    /// Direct URL of the site's main document.
+    ///
+    /// Defaults to the result of parsing a manifest URL with `.`.
+    pub document_url: Option<Url>,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Gets pending changes of the web app manifest.
+///
+/// Changes of sensitive manifest fields (name, short name, icons, scope and
+/// start URL) are not applied when updating the web app, and are kept pending
+/// until they are accepted or rejected by the user.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::ManifestChanges`] - List of pending manifest changes.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct GetManifestChanges {
+    /// A web app ID.
+    pub id: Ulid,
+}
+
+/// Accepts or rejects pending changes of the web app manifest.
+///
+/// Accepted changes are applied and the system integration is updated.
+/// Rejected changes are discarded until the manifest changes again.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::ManifestChangesResolved`] - No data.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ResolveManifestChanges {
+    /// A web app ID.
+    pub id: Ulid,
+
+    /// Whether the changes should be accepted or rejected.
+    pub accept: bool,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Pins or unpins the web app manifest.
+///
+/// Manifests of pinned web apps are not updated, but their
+/// system integration is still refreshed when updating them.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::ManifestPinned`] - No data.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct PinManifest {
+    /// A web app ID.
+    pub id: Ulid,
+
+    /// Whether the manifest should be pinned or unpinned.
+    pub pinned: bool,
+}
+
+/// Rolls back the web app manifest to a previous one from its manifest history.
+///
+/// The system integration is updated, and the manifest is pinned,
+/// so it is not immediately replaced by the next update.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::ManifestRolledBack`] - No data.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RollbackManifest {
+    /// A web app ID.
+    pub id: Ulid,
+
+    /// A manifest revision ID.
+    ///
+    /// If not specified, the newest previous manifest is used.
+    #[serde(default)]
+    pub revision: Option<Ulid>,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Exports web apps to a portable bundle.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SitesExported`] - A bundle with exported web apps and their profiles.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ExportSites {
+    /// IDs of web apps to export.
+    ///
+    /// If not specified or empty, all web apps are exported.
+    #[serde(default)]
+    pub sites: Vec<Ulid>,
+
+    /// Whether web app icons should be included in the bundle.
+    #[serde(default)]
+    pub icons: bool,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Imports web apps from a portable bundle.
+///
+/// All profiles (except the default profile) and web apps are created
+/// with new IDs, and system integration is installed for all web apps.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SitesImported`] - Generated IDs of the imported web apps.
+///
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct ImportSites {
+    /// A bundle to import.
+    pub bundle: Bundle,
+
+    /// A profile template.
+    ///
+    /// All contents of the provided template directory
+    /// will be copied to newly-created profiles.
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
+/// Discovers the web app manifest of a site from its page.
+///
+/// Returned manifest and document URLs can be used to install the web app.
+/// If the page does not link a manifest, the manifest can be synthesized from
+/// the page metadata, in which case a data URL with the manifest is returned.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::SiteDiscovered`] - Discovered manifest and document URLs.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct DiscoverSite {
+    /// URL of the site's page.
+    pub page: Url,
+
+    /// Whether the manifest should be synthesized if the page does not link one.
+    #[serde(default)]
+    pub synthesize_manifest: bool,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
 }
 
 /// Registers a custom protocol handler.
@@ -538,6 +879,66 @@ pub struct UnregisterProtocolHandler {
     pub handler: ProtocolHandlerResource,
 }
 
+/// Gets all available storage backups.
+///
+/// Backups are created automatically before every storage write.
+/// Only a limited number of the newest backups is kept.
+///
+/// # Parameters
+///
+/// None.
+///
+/// # Returns
+///
+/// [`ConnectorResponse::BackupList`] - List of backups, from the newest to the oldest.
+///
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct GetBackupList;
+
+/// Restores the storage from a backup.
+///
+/// System integration of web apps is not restored automatically.
+/// Web apps need to be updated to refresh their system integration.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::BackupRestored`] - No data.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct RestoreBackup {
+    /// A backup ID.
+    pub id: Ulid,
+}
+
+/// Checks the consistency of profiles, web apps and the runtime.
+///
+/// Finds inconsistencies between profiles and web apps, profile directories without
+/// profiles (and the reverse), missing system integration of web apps, and missing
+/// or unpatched runtime. Each found problem can optionally be repaired.
+///
+/// # Parameters
+///
+/// See [fields](#fields).
+///
+/// # Returns
+///
+/// [`ConnectorResponse::ConsistencyChecked`] - List of found problems.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct CheckConsistency {
+    /// Whether the found problems should be repaired (default: `false`).
+    #[serde(default)]
+    pub fix: bool,
+
+    /// Contains a HTTP client configuration.
+    #[serde(default)]
+    pub client: HTTPClientConfig,
+}
+
 /// Contains a HTTP client configuration.
 #[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
 pub struct HTTPClientConfig {
@@ -556,6 +957,38 @@ pub struct HTTPClientConfig {
     pub tls_danger_accept_invalid_hostnames: bool,
 }
 
+/// What to do if the same web app is already installed in the profile.
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateMode {
+    /// Fail without installing the web app.
+    Refuse,
+
+    /// Update the existing web app in place.
+    Update,
+
+    /// Install the web app again.
+    Allow,
+}
+
+impl Default for DuplicateMode {
+    #[inline]
+    fn default() -> Self {
+        Self::Allow
+    }
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<crate::console::app::DuplicateMode> for DuplicateMode {
+    fn into(self) -> crate::console::app::DuplicateMode {
+        match self {
+            Self::Refuse => crate::console::app::DuplicateMode::Refuse,
+            Self::Update => crate::console::app::DuplicateMode::Update,
+            Self::Allow => crate::console::app::DuplicateMode::Allow,
+        }
+    }
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<crate::console::app::HTTPClientConfig> for HTTPClientConfig {
     fn into(self) -> crate::console::app::HTTPClientConfig {
@@ -574,6 +1007,7 @@ deserialize_unit_struct!(InstallRuntime);
 deserialize_unit_struct!(UninstallRuntime);
 deserialize_unit_struct!(GetSiteList);
 deserialize_unit_struct!(GetProfileList);
+deserialize_unit_struct!(GetBackupList);
 
 build_request_enum!(
     GetSystemVersions,
@@ -586,11 +1020,24 @@ build_request_enum!(
     InstallSite,
     UninstallSite,
     UpdateSite,
+    MoveSite,
+    CloneSite,
     UpdateAllSites,
+    CheckSite,
+    GetManifestChanges,
+    ResolveManifestChanges,
+    PinManifest,
+    RollbackManifest,
+    ExportSites,
+    ImportSites,
+    DiscoverSite,
     GetProfileList,
     CreateProfile,
     RemoveProfile,
     UpdateProfile,
     RegisterProtocolHandler,
     UnregisterProtocolHandler,
+    GetBackupList,
+    RestoreBackup,
+    CheckConsistency,
 );
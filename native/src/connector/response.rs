@@ -3,8 +3,13 @@ use std::collections::BTreeMap;
 use serde::Serialize;
 use ulid::Ulid;
 
+use crate::components::bundle::Bundle;
+use crate::components::check::ManifestProblem;
+use crate::components::discovery::Discovery;
 use crate::components::profile::Profile;
-use crate::components::site::Site;
+use crate::components::site::{ManifestChange, Site};
+use crate::console::doctor::Problem;
+use crate::storage::backups::Backup;
 use crate::storage::Config;
 
 /// TODO: Docs
@@ -56,11 +61,47 @@ pub enum ConnectorResponse {
     /// Web app has been uninstalled.
     SiteUninstalled,
 
-    /// Web app has been updated.
-    SiteUpdated,
+    /// Web app has been updated, with the changes of its manifest.
+    ///
+    /// Sensitive changes are only applied once they are accepted.
+    SiteUpdated(Vec<ManifestChange>),
 
-    /// All web apps have been updated.
-    AllSitesUpdated,
+    /// Web app has been moved to another profile.
+    SiteMoved,
+
+    /// Web app has been cloned into another profile.
+    SiteCloned(Ulid),
+
+    /// All web apps have been updated, with the manifest changes of each changed web app.
+    ///
+    /// Sensitive changes are only applied once they are accepted.
+    AllSitesUpdated(BTreeMap<Ulid, Vec<ManifestChange>>),
+
+    /// List of problems found in the web app manifest.
+    SiteChecked(Vec<ManifestProblem>),
+
+    /// List of pending manifest changes of the web app.
+    ManifestChanges(Vec<ManifestChange>),
+
+    /// Pending manifest changes have been accepted or rejected.
+    ManifestChangesResolved,
+
+    /// Web app manifest has been pinned or unpinned.
+    ManifestPinned,
+
+    /// Web app manifest has been rolled back.
+    ManifestRolledBack,
+
+    /// Bundle with exported web apps and their profiles.
+    SitesExported(Bundle),
+
+    /// Web apps have been imported.
+    ///
+    /// Contains generated IDs of the imported web apps.
+    SitesImported(Vec<Ulid>),
+
+    /// Manifest and document URLs discovered from the page.
+    SiteDiscovered(Discovery),
 
     /// List of all available profiles.
     ProfileList(BTreeMap<Ulid, Profile>),
@@ -80,6 +121,17 @@ pub enum ConnectorResponse {
     /// Protocol handler has been unregistered.
     ProtocolHandlerUnregistered,
 
+    /// List of all available storage backups.
+    BackupList(Vec<Backup>),
+
+    /// Storage has been restored from a backup.
+    BackupRestored,
+
+    /// Consistency has been checked.
+    ///
+    /// Contains all found problems and whether they have been fixed.
+    ConsistencyChecked(Vec<Problem>),
+
     /// Something went wrong...
     Error(String),
 }
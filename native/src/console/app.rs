@@ -1,8 +1,9 @@
 #![allow(clippy::large_enum_variant)]
 
 use std::path::PathBuf;
+use std::time::Duration;
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, ValueEnum};
 use ulid::Ulid;
 use url::Url;
 
@@ -21,10 +22,23 @@ pub enum App {
     /// Manage the runtime
     #[clap(subcommand)]
     Runtime(RuntimeCommand),
+
+    /// Manage the storage
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+
+    /// Check the consistency of profiles, web apps and the runtime
+    Doctor(DoctorCommand),
+
+    /// Apply profiles and web apps from a desired-state file
+    Apply(ApplyCommand),
 }
 
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
 pub enum SiteCommand {
+    /// List installed web apps, from the most recently used
+    List(SiteListCommand),
+
     /// Launch a web app
     Launch(SiteLaunchCommand),
 
@@ -34,10 +48,35 @@ pub enum SiteCommand {
     /// Uninstall a web app
     Uninstall(SiteUninstallCommand),
 
+    /// Uninstall web apps that have not been used for a long time
+    Prune(SitePruneCommand),
+
     /// Update a web app
     Update(SiteUpdateCommand),
+
+    /// Move a web app to another profile
+    Move(SiteMoveCommand),
+
+    /// Clone a web app into another profile
+    Clone(SiteCloneCommand),
+
+    /// Check a web app manifest for problems
+    Check(SiteCheckCommand),
+
+    /// Manage the web app manifest history
+    #[clap(subcommand)]
+    Manifest(SiteManifestCommand),
+
+    /// Export web apps to a portable bundle
+    Export(SiteExportCommand),
+
+    /// Import web apps from a portable bundle
+    Import(SiteImportCommand),
 }
 
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteListCommand {}
+
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
 pub struct SiteLaunchCommand {
     /// Web app ID
@@ -47,13 +86,24 @@ pub struct SiteLaunchCommand {
     pub arguments: Vec<String>,
 
     /// Launch web app on a custom start URL
-    #[clap(long, conflicts_with = "protocol", value_hint = clap::ValueHint::Url)]
+    #[clap(long, conflicts_with_all = ["protocol", "file", "share"], value_hint = clap::ValueHint::Url)]
     pub url: Option<Url>,
 
     /// Launch web app on a protocol handler URL
-    #[clap(long, conflicts_with = "url", value_hint = clap::ValueHint::Url)]
+    #[clap(long, conflicts_with_all = ["url", "file", "share"], value_hint = clap::ValueHint::Url)]
     pub protocol: Option<Option<Url>>,
 
+    /// Launch web app on a file handler for the files
+    /// {n}Uses the first enabled file handler that accepts all files
+    /// {n}Files are not passed to the web app, as Firefox does not support it yet
+    #[clap(long, num_args = 1.., conflicts_with_all = ["url", "protocol", "share"], value_hint = clap::ValueHint::FilePath)]
+    pub file: Vec<PathBuf>,
+
+    /// Launch web app on its share target with the shared data
+    /// {n}Specified as `title=...,text=...,url=...`, where all fields are optional
+    #[clap(long, conflicts_with_all = ["url", "protocol", "file"])]
+    pub share: Vec<String>,
+
     /// Internal: Directly launch web app without system integration
     #[cfg(target_os = "macos")]
     #[clap(long, hide = true)]
@@ -63,14 +113,24 @@ pub struct SiteLaunchCommand {
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
 pub struct SiteInstallCommand {
     /// Direct URL of the site's web app manifest
-    #[clap(value_hint = clap::ValueHint::Url)]
-    pub manifest_url: Url,
+    #[clap(required_unless_present = "page", value_hint = clap::ValueHint::Url)]
+    pub manifest_url: Option<Url>,
 
     /// Direct URL of the site's main document
     /// {n}Defaults to the result of parsing a manifest URL with `.`
     #[clap(long, value_hint = clap::ValueHint::Url)]
     pub document_url: Option<Url>,
 
+    /// URL of the site's page that links the web app manifest
+    /// {n}Used to discover the manifest and document URLs
+    #[clap(long, conflicts_with_all = ["manifest_url", "document_url"], value_hint = clap::ValueHint::Url)]
+    pub page: Option<Url>,
+
+    /// Synthesize the web app manifest from the page metadata
+    /// {n}Only used when the page does not link a manifest
+    #[clap(long, requires = "page")]
+    pub synthesize_manifest: bool,
+
     /// Profile where this web app will be installed
     /// {n}Defaults to the shared profile
     #[clap(long)]
@@ -108,6 +168,11 @@ pub struct SiteInstallCommand {
     #[clap(long)]
     pub launch_on_browser: Option<bool>,
 
+    /// What to do if the same web app is already installed in the profile
+    /// {n}Web apps are matched by their manifest ID, or start URL if it is not set
+    #[clap(long, value_enum, default_value_t = DuplicateMode::Refuse)]
+    pub duplicate: DuplicateMode,
+
     /// Disable system integration
     #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
     pub system_integration: bool,
@@ -117,6 +182,18 @@ pub struct SiteInstallCommand {
     pub client: HTTPClientConfig,
 }
 
+#[derive(ValueEnum, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DuplicateMode {
+    /// Fail without installing the web app
+    Refuse,
+
+    /// Update the existing web app in place
+    Update,
+
+    /// Install the web app again
+    Allow,
+}
+
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
 pub struct SiteUninstallCommand {
     /// Web app ID
@@ -131,6 +208,80 @@ pub struct SiteUninstallCommand {
     pub system_integration: bool,
 }
 
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SitePruneCommand {
+    /// Time since the last use after which web apps are uninstalled
+    /// {n}Specified as a number with `h` (hours), `d` (days) or `w` (weeks)
+    /// {n}Web apps that have never been launched were last used when installed
+    #[clap(long, default_value = "90d", value_parser = parse_duration)]
+    pub unused_for: Duration,
+
+    /// Disable any interactive prompts
+    #[clap(short, long)]
+    pub quiet: bool,
+
+    /// Only print web apps that would be uninstalled
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Disable system integration
+    #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
+    pub system_integration: bool,
+}
+
+/// Parses a duration specified as a number with an `h`, `d` or `w` unit.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let index = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(index);
+
+    let number: u64 = number.parse().map_err(|_| format!("invalid number in `{value}`"))?;
+    let seconds = match unit {
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err("expected a number followed by `h`, `d` or `w`".into()),
+    };
+
+    number
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| "duration is too long".into())
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteMoveCommand {
+    /// Web app ID
+    pub id: Ulid,
+
+    /// Profile where the web app should be moved
+    /// {n}The web app keeps its ID, so launchers and shortcuts keep working
+    #[clap(long)]
+    pub profile: Ulid,
+
+    /// Disable system integration
+    #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
+    pub system_integration: bool,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteCloneCommand {
+    /// Web app ID
+    pub id: Ulid,
+
+    /// Profile where the web app should be cloned
+    /// {n}The clone gets a new ID and the same settings as the web app
+    #[clap(long)]
+    pub profile: Ulid,
+
+    /// Disable system integration
+    #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
+    pub system_integration: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
 pub struct SiteUpdateCommand {
     /// Web app ID
@@ -160,6 +311,12 @@ pub struct SiteUpdateCommand {
     #[clap(long)]
     pub keywords: Option<Vec<String>>,
 
+    /// Set a locale for the web app name and description
+    /// {n}Specified as a language tag, such as `de` or `pt-BR`
+    /// {n}Without a value, the default manifest values are used
+    #[clap(long)]
+    pub locale: Option<Option<String>>,
+
     /// Set enabled URL handlers
     #[clap(long)]
     pub enabled_url_handlers: Option<Vec<String>>,
@@ -168,6 +325,11 @@ pub struct SiteUpdateCommand {
     #[clap(long)]
     pub enabled_protocol_handlers: Option<Vec<String>>,
 
+    /// Set enabled file handlers
+    /// {n}Each handler is specified by its action URL
+    #[clap(long)]
+    pub enabled_file_handlers: Option<Vec<String>>,
+
     /// Set the web app to launch on the system login.
     #[clap(long)]
     pub launch_on_login: Option<bool>,
@@ -176,6 +338,32 @@ pub struct SiteUpdateCommand {
     #[clap(long)]
     pub launch_on_browser: Option<bool>,
 
+    /// Set arguments passed to the runtime for this web app
+    /// {n}Use an empty string to remove all arguments
+    #[clap(long, allow_hyphen_values = true)]
+    pub arguments: Option<Vec<String>>,
+
+    /// Set environment variables passed to the runtime for this web app
+    /// {n}Each variable is specified as `NAME=VALUE`
+    /// {n}Use an empty string to remove all variables
+    #[clap(long)]
+    pub variables: Option<Vec<String>>,
+
+    /// Overwrite the Wayland Display Server option for this web app
+    /// {n}Without a value, the config value is used
+    #[clap(long)]
+    pub runtime_enable_wayland: Option<Option<bool>>,
+
+    /// Overwrite the XInput2 option for this web app
+    /// {n}Without a value, the config value is used
+    #[clap(long)]
+    pub runtime_use_xinput2: Option<Option<bool>>,
+
+    /// Overwrite the XDG Desktop Portals option for this web app
+    /// {n}Without a value, the config value is used
+    #[clap(long)]
+    pub runtime_use_portals: Option<Option<bool>>,
+
     /// Disable manifest updates
     #[clap(long = "no-manifest-updates", action = ArgAction::SetFalse)]
     pub update_manifest: bool,
@@ -184,6 +372,129 @@ pub struct SiteUpdateCommand {
     #[clap(long = "no-icon-updates", action = ArgAction::SetFalse)]
     pub update_icons: bool,
 
+    /// Accept changes of sensitive manifest fields
+    /// {n}Sensitive fields are the name, short name, icons, scope, start URL, ID, file handlers,
+    /// share target, launch handler and translated members
+    #[clap(long, conflicts_with = "reject_manifest_changes")]
+    pub accept_manifest_changes: bool,
+
+    /// Reject pending changes of sensitive manifest fields
+    #[clap(long)]
+    pub reject_manifest_changes: bool,
+
+    /// Disable system integration
+    #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
+    pub system_integration: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteCheckCommand {
+    /// Web app ID
+    #[clap(required_unless_present = "manifest_url", conflicts_with = "manifest_url")]
+    pub id: Option<Ulid>,
+
+    /// Direct URL of a web app manifest to check instead of an installed web app
+    #[clap(long, value_hint = clap::ValueHint::Url)]
+    pub manifest_url: Option<Url>,
+
+    /// Direct URL of the site's main document
+    /// {n}Defaults to the result of parsing a manifest URL with `.`
+    #[clap(long, requires = "manifest_url", value_hint = clap::ValueHint::Url)]
+    pub document_url: Option<Url>,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteExportCommand {
+    /// Path where the bundle will be saved
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub output: PathBuf,
+
+    /// Web app to export
+    /// {n}Can be specified multiple times
+    /// {n}Defaults to all web apps
+    #[clap(long = "site")]
+    pub sites: Vec<Ulid>,
+
+    /// Include web app icons in the bundle
+    #[clap(long)]
+    pub icons: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteImportCommand {
+    /// Path of the bundle to import
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub input: PathBuf,
+
+    /// Set a template for created profiles
+    /// {n}All contents of the template directory
+    /// will be copied to newly-created profiles
+    #[clap(long, value_hint = clap::ValueHint::DirPath)]
+    pub template: Option<PathBuf>,
+
+    /// Disable system integration
+    #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
+    pub system_integration: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub enum SiteManifestCommand {
+    /// List previous web app manifests
+    History(SiteManifestHistoryCommand),
+
+    /// Pin the web app manifest to skip manifest updates
+    Pin(SiteManifestPinCommand),
+
+    /// Unpin the web app manifest to allow manifest updates
+    Unpin(SiteManifestUnpinCommand),
+
+    /// Roll back the web app manifest to a previous one
+    Rollback(SiteManifestRollbackCommand),
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteManifestHistoryCommand {
+    /// Web app ID
+    pub id: Ulid,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteManifestPinCommand {
+    /// Web app ID
+    pub id: Ulid,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteManifestUnpinCommand {
+    /// Web app ID
+    pub id: Ulid,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct SiteManifestRollbackCommand {
+    /// Web app ID
+    pub id: Ulid,
+
+    /// Manifest revision ID
+    /// {n}Defaults to the newest previous manifest
+    pub revision: Option<Ulid>,
+
     /// Disable system integration
     #[clap(long = "no-system-integration", action = ArgAction::SetFalse)]
     pub system_integration: bool,
@@ -250,6 +561,17 @@ pub struct ProfileUpdateCommand {
     /// Set a profile description
     #[clap(long)]
     pub description: Option<Option<String>>,
+
+    /// Set arguments passed to the runtime for this profile
+    /// {n}Use an empty string to remove all arguments
+    #[clap(long, allow_hyphen_values = true)]
+    pub arguments: Option<Vec<String>>,
+
+    /// Set environment variables passed to the runtime for this profile
+    /// {n}Each variable is specified as `NAME=VALUE`
+    /// {n}Use an empty string to remove all variables
+    #[clap(long)]
+    pub variables: Option<Vec<String>>,
 }
 
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
@@ -268,6 +590,62 @@ pub struct RuntimeInstallCommand {}
 pub struct RuntimeUninstallCommand {}
 
 #[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub enum ConfigCommand {
+    /// Manage storage backups
+    #[clap(subcommand)]
+    Backups(ConfigBackupsCommand),
+
+    /// Restore the storage from a backup
+    Restore(ConfigRestoreCommand),
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub enum ConfigBackupsCommand {
+    /// List available storage backups
+    List(ConfigBackupsListCommand),
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct ConfigBackupsListCommand {}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct ConfigRestoreCommand {
+    /// Backup ID
+    pub id: Ulid,
+
+    /// Disable any interactive prompts
+    #[clap(short, long)]
+    pub quiet: bool,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct DoctorCommand {
+    /// Repair all found problems
+    #[clap(long)]
+    pub fix: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone)]
+pub struct ApplyCommand {
+    /// Path of the desired-state file
+    /// {n}Parsed as TOML if it has the `.toml` extension, otherwise as JSON
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    pub file: PathBuf,
+
+    /// Only print the plan without applying it
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Configuration of the HTTP client.
+    #[clap(flatten)]
+    pub client: HTTPClientConfig,
+}
+
+#[derive(Parser, Debug, Eq, PartialEq, Clone, Default)]
 pub struct HTTPClientConfig {
     /// Import additional root certificates from a DER file
     #[clap(long, value_hint = clap::ValueHint::FilePath)]
@@ -0,0 +1,475 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::Deserialize;
+use ulid::Ulid;
+use url::Url;
+
+use crate::components::profile::Profile;
+use crate::components::site::Site;
+use crate::console::app::{
+    ApplyCommand,
+    DuplicateMode,
+    ProfileCreateCommand,
+    ProfileUpdateCommand,
+    SiteInstallCommand,
+    SiteUninstallCommand,
+    SiteUpdateCommand,
+};
+use crate::console::Run;
+use crate::directories::ProjectDirs;
+use crate::storage::Storage;
+
+const READ_ERROR: &str = "Failed to read desired-state file";
+const PARSE_ERROR: &str = "Failed to parse desired-state file";
+
+/// Contains a profile from the desired-state file.
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredProfile {
+    /// A profile name.
+    pub name: Option<String>,
+
+    /// A profile description.
+    pub description: Option<String>,
+
+    /// Arguments to be passed to the Firefox runtime for this profile.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime for this profile.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl DesiredProfile {
+    /// Checks whether the profile differs from the desired one.
+    fn differs(&self, profile: &Profile) -> bool {
+        profile.name != self.name
+            || profile.description != self.description
+            || profile.arguments != self.arguments
+            || profile.variables != self.variables
+    }
+}
+
+/// Contains a web app from the desired-state file.
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredSite {
+    /// Direct URL of the site's web app manifest.
+    pub manifest_url: Url,
+
+    /// Direct URL of the site's main document.
+    ///
+    /// Defaults to the result of parsing a manifest URL with `.`.
+    pub document_url: Option<Url>,
+
+    /// A key of the profile where this web app is installed.
+    ///
+    /// Defaults to the shared profile.
+    pub profile: Option<String>,
+
+    /// A custom web app name.
+    pub name: Option<String>,
+
+    /// A custom web app description.
+    pub description: Option<String>,
+
+    /// A custom web app start URL.
+    pub start_url: Option<Url>,
+
+    /// A custom web app icon URL.
+    pub icon_url: Option<Url>,
+
+    /// Custom web app categories.
+    pub categories: Option<Vec<String>>,
+
+    /// Custom web app keywords.
+    pub keywords: Option<Vec<String>>,
+
+    /// Whether the web app should be launched on the system login.
+    #[serde(default)]
+    pub launch_on_login: bool,
+
+    /// Whether the web app should be launched on the browser launch.
+    #[serde(default)]
+    pub launch_on_browser: bool,
+
+    /// Enabled URL handlers.
+    #[serde(default)]
+    pub enabled_url_handlers: Vec<String>,
+
+    /// Enabled protocol handlers.
+    #[serde(default)]
+    pub enabled_protocol_handlers: Vec<String>,
+
+    /// Enabled file handlers.
+    #[serde(default)]
+    pub enabled_file_handlers: Vec<String>,
+
+    /// Arguments to be passed to the Firefox runtime for this web app.
+    #[serde(default)]
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime for this web app.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+impl DesiredSite {
+    fn document_url(&self) -> Result<Url> {
+        match &self.document_url {
+            Some(url) => Ok(url.clone()),
+            None => Ok(self.manifest_url.join(".")?),
+        }
+    }
+
+    /// Checks whether the web app config differs from the desired one.
+    ///
+    /// Only checks fields that can be changed without reinstalling the web app.
+    fn differs(&self, site: &Site) -> bool {
+        let config = &site.config;
+
+        config.name != self.name
+            || config.description != self.description
+            || config.start_url != self.start_url
+            || config.icon_url != self.icon_url
+            || config.categories != self.categories
+            || config.keywords != self.keywords
+            || config.launch_on_login != self.launch_on_login
+            || config.launch_on_browser != self.launch_on_browser
+            || config.enabled_url_handlers != self.enabled_url_handlers
+            || config.enabled_protocol_handlers != self.enabled_protocol_handlers
+            || config.enabled_file_handlers != self.enabled_file_handlers
+            || config.arguments != self.arguments
+            || config.variables != self.variables
+    }
+}
+
+/// Contains the desired state of profiles and web apps.
+///
+/// Profiles and web apps are identified by user-chosen keys, which are
+/// stored with them once they are created. Only profiles and web apps
+/// with keys are managed by the desired-state file.
+#[derive(Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    /// Profiles by their keys.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, DesiredProfile>,
+
+    /// Web apps by their keys.
+    #[serde(default)]
+    pub sites: BTreeMap<String, DesiredSite>,
+}
+
+impl DesiredState {
+    /// Loads the desired-state file.
+    ///
+    /// Files with the `.toml` extension are parsed as TOML,
+    /// and all other files are parsed as JSON.
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = read_to_string(path).context(READ_ERROR)?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => toml::from_str(&data).context(PARSE_ERROR),
+            _ => serde_json::from_str(&data).context(PARSE_ERROR),
+        }
+    }
+}
+
+/// An action needed to converge the storage to the desired state.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Action {
+    CreateProfile { key: String },
+    UpdateProfile { key: String, id: Ulid },
+    InstallSite { key: String },
+    UpdateSite { key: String, id: Ulid, update_icons: bool },
+    ReinstallSite { key: String, id: Ulid },
+    UninstallSite { key: String, id: Ulid },
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateProfile { key } => write!(f, "Create profile `{}`", key),
+            Self::UpdateProfile { key, id } => write!(f, "Update profile `{}` ({})", key, id),
+            Self::InstallSite { key } => write!(f, "Install web app `{}`", key),
+            Self::UpdateSite { key, id, .. } => write!(f, "Update web app `{}` ({})", key, id),
+            Self::ReinstallSite { key, id } => write!(f, "Reinstall web app `{}` ({})", key, id),
+            Self::UninstallSite { key, id } => write!(f, "Uninstall web app `{}` ({})", key, id),
+        }
+    }
+}
+
+/// Converts a desired list to a command parameter that replaces the stored list.
+///
+/// See [`crate::console::store_value_list`] for why an empty list is converted to an empty string.
+fn list_parameter(values: &[String]) -> Vec<String> {
+    if values.is_empty() {
+        vec!["".into()]
+    } else {
+        values.to_vec()
+    }
+}
+
+/// Converts desired environment variables to a command parameter in the `NAME=VALUE` format.
+fn variables_parameter(variables: &BTreeMap<String, String>) -> Vec<String> {
+    let variables: Vec<_> =
+        variables.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    list_parameter(&variables)
+}
+
+fn find_profile<'a>(storage: &'a Storage, key: &str) -> Option<&'a Profile> {
+    storage.profiles.values().find(|profile| profile.key.as_deref() == Some(key))
+}
+
+fn find_site<'a>(storage: &'a Storage, key: &str) -> Option<&'a Site> {
+    storage.sites.values().find(|site| site.config.key.as_deref() == Some(key))
+}
+
+/// Computes actions needed to converge the storage to the desired state.
+///
+/// Profiles are never removed, as they contain user data. Web apps with
+/// a different manifest URL, document URL or profile are reinstalled,
+/// and managed web apps that are not in the desired state are uninstalled.
+pub fn plan(state: &DesiredState, storage: &Storage) -> Result<Vec<Action>> {
+    let mut actions = vec![];
+
+    for (key, desired) in &state.profiles {
+        match find_profile(storage, key) {
+            Some(profile) if desired.differs(profile) => {
+                actions.push(Action::UpdateProfile { key: key.clone(), id: profile.ulid })
+            }
+            Some(_) => {}
+            None => actions.push(Action::CreateProfile { key: key.clone() }),
+        }
+    }
+
+    for (key, desired) in &state.sites {
+        let profile = match &desired.profile {
+            Some(profile) if !state.profiles.contains_key(profile) => {
+                bail!("Web app `{}` uses unknown profile `{}`", key, profile)
+            }
+            Some(profile) => find_profile(storage, profile).map(|profile| profile.ulid),
+            None => Some(Ulid::nil()),
+        };
+
+        let site = match find_site(storage, key) {
+            Some(site) => site,
+            None => {
+                actions.push(Action::InstallSite { key: key.clone() });
+                continue;
+            }
+        };
+
+        if profile != Some(site.profile)
+            || site.config.manifest_url != desired.manifest_url
+            || site.config.document_url != desired.document_url()?
+        {
+            actions.push(Action::ReinstallSite { key: key.clone(), id: site.ulid });
+        } else if desired.differs(site) {
+            let update_icons =
+                site.config.icon_url != desired.icon_url || site.config.name != desired.name;
+            actions.push(Action::UpdateSite { key: key.clone(), id: site.ulid, update_icons });
+        }
+    }
+
+    for site in storage.sites.values() {
+        if let Some(key) = &site.config.key {
+            if !state.sites.contains_key(key) {
+                actions.push(Action::UninstallSite { key: key.clone(), id: site.ulid });
+            }
+        }
+    }
+
+    Ok(actions)
+}
+
+impl Run for ApplyCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let state = DesiredState::load(&self.file)?;
+
+        // Storage is only needed to compute the plan
+        // Commands used to apply it will lock the storage themselves
+        let storage = Storage::load(&dirs)?.unlock();
+        let actions = plan(&state, &storage)?;
+
+        if actions.is_empty() {
+            info!("Nothing to apply");
+            return Ok(());
+        }
+
+        for action in &actions {
+            println!("- {}", action);
+        }
+
+        if self.dry_run {
+            info!("Dry run, nothing has been changed");
+            return Ok(());
+        }
+
+        let mut profiles: BTreeMap<String, Ulid> = storage
+            .profiles
+            .values()
+            .filter_map(|profile| Some((profile.key.clone()?, profile.ulid)))
+            .collect();
+
+        for action in actions {
+            info!("{}", action);
+
+            match action {
+                Action::CreateProfile { key } => {
+                    let ulid = self.create_profile(&dirs, &key, &state.profiles[&key])?;
+                    profiles.insert(key, ulid);
+                }
+                Action::UpdateProfile { key, id } => {
+                    let desired = &state.profiles[&key];
+                    let command = ProfileUpdateCommand {
+                        id,
+                        name: Some(desired.name.clone()),
+                        description: Some(desired.description.clone()),
+                        arguments: Some(list_parameter(&desired.arguments)),
+                        variables: Some(variables_parameter(&desired.variables)),
+                    };
+                    command.run()?;
+                }
+                Action::InstallSite { key } => {
+                    self.install_site(&dirs, &key, &state.sites[&key], &profiles)?;
+                }
+                Action::UpdateSite { key, id, update_icons } => {
+                    self.update_site(id, &state.sites[&key], update_icons)?;
+                }
+                Action::ReinstallSite { key, id } => {
+                    self.uninstall_site(id)?;
+                    self.install_site(&dirs, &key, &state.sites[&key], &profiles)?;
+                }
+                Action::UninstallSite { id, .. } => {
+                    self.uninstall_site(id)?;
+                }
+            }
+        }
+
+        info!("Desired state applied!");
+        Ok(())
+    }
+}
+
+impl ApplyCommand {
+    fn create_profile(
+        &self,
+        dirs: &ProjectDirs,
+        key: &str,
+        desired: &DesiredProfile,
+    ) -> Result<Ulid> {
+        let command = ProfileCreateCommand {
+            name: desired.name.clone(),
+            description: desired.description.clone(),
+            template: None,
+        };
+        let ulid = command._run()?;
+
+        // Store the key immediately, so the profile is not created again if applying fails
+        let mut storage = Storage::load(dirs)?;
+        let profile = storage.profiles.get_mut(&ulid).context("Profile does not exist")?;
+        profile.key = Some(key.into());
+        profile.arguments = desired.arguments.clone();
+        profile.variables = desired.variables.clone();
+        storage.write(dirs)?;
+
+        Ok(ulid)
+    }
+
+    fn install_site(
+        &self,
+        dirs: &ProjectDirs,
+        key: &str,
+        desired: &DesiredSite,
+        profiles: &BTreeMap<String, Ulid>,
+    ) -> Result<()> {
+        let profile = match &desired.profile {
+            Some(profile) => *profiles.get(profile).context("Profile does not exist")?,
+            None => Ulid::nil(),
+        };
+
+        let command = SiteInstallCommand {
+            manifest_url: Some(desired.manifest_url.clone()),
+            document_url: desired.document_url.clone(),
+            page: None,
+            synthesize_manifest: false,
+            duplicate: DuplicateMode::Allow,
+            profile: Some(profile),
+            start_url: desired.start_url.clone(),
+            icon_url: desired.icon_url.clone(),
+            name: desired.name.clone(),
+            description: desired.description.clone(),
+            categories: desired.categories.clone(),
+            keywords: desired.keywords.clone(),
+            launch_on_login: Some(desired.launch_on_login),
+            launch_on_browser: Some(desired.launch_on_browser),
+            system_integration: true,
+            client: self.client.clone(),
+        };
+        let ulid = command._run()?;
+
+        // Store the key immediately, so the web app is not installed again if applying fails
+        // Launch options are not used by the system integration, so they can be stored directly
+        let mut storage = Storage::load(dirs)?;
+        let site = storage.sites.get_mut(&ulid).context("Web app does not exist")?;
+        site.config.key = Some(key.into());
+        site.config.arguments = desired.arguments.clone();
+        site.config.variables = desired.variables.clone();
+        storage.write(dirs)?;
+
+        // Handlers can only be enabled once the web app is installed
+        if !desired.enabled_url_handlers.is_empty()
+            || !desired.enabled_protocol_handlers.is_empty()
+            || !desired.enabled_file_handlers.is_empty()
+        {
+            self.update_site(ulid, desired, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn update_site(&self, id: Ulid, desired: &DesiredSite, update_icons: bool) -> Result<()> {
+        // See [`crate::console::store_value_vec`] for why `None` is converted to an empty string
+        let command = SiteUpdateCommand {
+            id,
+            start_url: Some(desired.start_url.clone()),
+            icon_url: Some(desired.icon_url.clone()),
+            name: Some(desired.name.clone()),
+            description: Some(desired.description.clone()),
+            categories: Some(desired.categories.clone().unwrap_or_else(|| vec!["".into()])),
+            keywords: Some(desired.keywords.clone().unwrap_or_else(|| vec!["".into()])),
+            locale: None,
+            enabled_url_handlers: Some(desired.enabled_url_handlers.clone()),
+            enabled_protocol_handlers: Some(desired.enabled_protocol_handlers.clone()),
+            enabled_file_handlers: Some(desired.enabled_file_handlers.clone()),
+            launch_on_login: Some(desired.launch_on_login),
+            launch_on_browser: Some(desired.launch_on_browser),
+            arguments: Some(list_parameter(&desired.arguments)),
+            variables: Some(variables_parameter(&desired.variables)),
+            runtime_enable_wayland: None,
+            runtime_use_xinput2: None,
+            runtime_use_portals: None,
+            update_manifest: false,
+            update_icons,
+            accept_manifest_changes: false,
+            reject_manifest_changes: false,
+            system_integration: true,
+            client: self.client.clone(),
+        };
+        command.run()
+    }
+
+    fn uninstall_site(&self, id: Ulid) -> Result<()> {
+        let command = SiteUninstallCommand { id, quiet: true, system_integration: true };
+        command.run()
+    }
+}
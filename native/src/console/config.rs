@@ -0,0 +1,69 @@
+use std::io;
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::console::app::{ConfigBackupsListCommand, ConfigRestoreCommand};
+use crate::console::Run;
+use crate::directories::ProjectDirs;
+use crate::storage::{backups, Storage};
+
+impl Run for ConfigBackupsListCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let backups = backups::list(&dirs)?;
+
+        if backups.is_empty() {
+            info!("No backups available");
+            return Ok(());
+        }
+
+        for backup in backups {
+            let created = OffsetDateTime::from(backup.id.datetime())
+                .format(&Rfc3339)
+                .context("Failed to format backup time")?;
+
+            println!("- {}: {} ({} bytes)", backup.id, created, backup.size);
+        }
+
+        Ok(())
+    }
+}
+
+impl Run for ConfigRestoreCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+
+        // The storage is only loaded after the confirmation, so it is not locked while waiting
+        if !self.quiet {
+            warn!("This will replace all profiles, web apps and settings with the backup");
+            warn!("The current storage will be backed up, so this can be undone");
+
+            print!("Do you want to continue (y/n)? ");
+            io::stdout().flush()?;
+
+            let mut confirm = String::new();
+            io::stdin().read_line(&mut confirm)?;
+            confirm = confirm.trim().into();
+
+            if confirm != "Y" && confirm != "y" {
+                info!("Aborting!");
+                return Ok(());
+            }
+        }
+
+        info!("Restoring the storage");
+        let mut storage = Storage::load(&dirs)?;
+        storage.restore(&dirs, &self.id)?;
+        storage.write(&dirs)?;
+
+        warn!("System integration is not restored automatically");
+        warn!("Update web apps to refresh their system integration");
+
+        info!("Storage restored!");
+        Ok(())
+    }
+}
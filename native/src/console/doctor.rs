@@ -0,0 +1,362 @@
+use std::collections::btree_map::Entry;
+use std::fs::read_dir;
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::Serialize;
+use ulid::Ulid;
+
+use crate::components::profile::Profile;
+use crate::components::runtime::Runtime;
+use crate::components::site::Site;
+use crate::console::app::{DoctorCommand, RuntimeInstallCommand};
+use crate::console::site::preinstall_policy_sites;
+use crate::console::Run;
+use crate::directories::ProjectDirs;
+use crate::integrations;
+use crate::integrations::{IntegrationInstallArgs, IntegrationVerifyArgs};
+use crate::storage::Storage;
+use crate::utils::construct_certificates_and_client;
+
+/// A kind of problem found by the consistency checker.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ProblemKind {
+    /// Profile lists a web app that does not exist.
+    DanglingSite,
+
+    /// Profile lists a web app that belongs to another profile.
+    MisplacedSite,
+
+    /// Web app belongs to a profile that does not exist.
+    SiteWithoutProfile,
+
+    /// Web app is not listed in its profile.
+    UnlistedSite,
+
+    /// Profile directory exists, but the profile is not in the storage.
+    OrphanedProfileDirectory,
+
+    /// Profile is in the storage, but its directory does not exist.
+    MissingProfileDirectory,
+
+    /// Profile has not been patched with the latest UserChrome modifications.
+    OutdatedProfilePatch,
+
+    /// Web app manifest does not have a valid start URL or scope.
+    MalformedSite,
+
+    /// System integration of a web app is (partially) missing.
+    MissingIntegration,
+
+    /// Runtime is not installed.
+    MissingRuntime,
+
+    /// Runtime has not been patched with the UserChrome modifications.
+    UnpatchedRuntime,
+
+    /// Web app from the policy has not been preinstalled yet.
+    MissingPreinstalledSite,
+}
+
+/// A problem found by the consistency checker.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct Problem {
+    /// A kind of the problem.
+    pub kind: ProblemKind,
+
+    /// A human-readable description of the problem.
+    pub description: String,
+
+    /// Whether the problem has been fixed.
+    pub fixed: bool,
+}
+
+/// Logs the error of a failed fix and returns whether the fix succeeded.
+fn succeeded<T>(result: Result<T>) -> bool {
+    match result {
+        Ok(_) => true,
+        Err(error) => {
+            error!("{:?}", error);
+            false
+        }
+    }
+}
+
+impl Run for DoctorCommand {
+    fn run(&self) -> Result<()> {
+        let problems = self._run()?;
+
+        if problems.is_empty() {
+            info!("No problems found!");
+            return Ok(());
+        }
+
+        for problem in &problems {
+            let status = if problem.fixed { "fixed" } else { "found" };
+            println!("- [{}] {}", status, problem.description);
+        }
+
+        if !self.fix {
+            info!("Run this command with `--fix` to repair the problems");
+        }
+
+        Ok(())
+    }
+}
+
+impl DoctorCommand {
+    pub fn _run(&self) -> Result<Vec<Problem>> {
+        let dirs = ProjectDirs::new()?;
+        let mut storage = Storage::load(&dirs)?;
+        let mut problems = vec![];
+
+        let mut report = |kind: ProblemKind, description: String, fixed: bool| {
+            if fixed {
+                info!("Fixed: {}", description);
+            } else {
+                warn!("{}", description);
+            }
+
+            problems.push(Problem { kind, description, fixed });
+        };
+
+        // Check web apps listed in profiles
+        let mut dangling = vec![];
+        for profile in storage.profiles.values_mut() {
+            let sites = &storage.sites;
+
+            for id in &profile.sites {
+                match sites.get(id) {
+                    Some(site) if site.profile != profile.ulid => report(
+                        ProblemKind::MisplacedSite,
+                        format!("Profile {} lists web app {} of another profile", profile.ulid, id),
+                        self.fix,
+                    ),
+                    Some(_) => {}
+                    None => {
+                        report(
+                            ProblemKind::DanglingSite,
+                            format!("Profile {} lists non-existing web app {}", profile.ulid, id),
+                            self.fix,
+                        );
+                        dangling.push(*id);
+                    }
+                }
+            }
+
+            if self.fix {
+                let ulid = profile.ulid;
+                profile
+                    .sites
+                    .retain(|id| matches!(sites.get(id), Some(site) if site.profile == ulid));
+            }
+        }
+
+        // Web apps whose files are missing also need to be removed from the storage index
+        if self.fix {
+            for id in dangling {
+                storage.forget_missing_site(&id);
+            }
+        }
+
+        // Check profiles of web apps
+        for site in storage.sites.values_mut() {
+            if !storage.profiles.contains_key(&site.profile) {
+                report(
+                    ProblemKind::SiteWithoutProfile,
+                    format!(
+                        "Web app {} belongs to non-existing profile {}",
+                        site.ulid, site.profile
+                    ),
+                    self.fix,
+                );
+
+                // Such web apps are moved to the default profile
+                if self.fix {
+                    site.profile = Ulid::nil();
+                }
+            }
+
+            if let Some(profile) = storage.profiles.get_mut(&site.profile) {
+                if !profile.sites.contains(&site.ulid) {
+                    report(
+                        ProblemKind::UnlistedSite,
+                        format!("Web app {} is not listed in profile {}", site.ulid, site.profile),
+                        self.fix,
+                    );
+
+                    if self.fix {
+                        profile.sites.push(site.ulid);
+                    }
+                }
+            }
+        }
+
+        // Check profile directories that are not in the storage
+        let directory = dirs.userdata.join("profiles");
+        if directory.exists() {
+            for entry in read_dir(directory).context("Failed to read profiles directory")? {
+                let entry = entry.context("Failed to read profiles directory")?;
+                let ulid = match entry.file_name().to_str().map(Ulid::from_string) {
+                    Some(Ok(ulid)) => ulid,
+                    _ => continue,
+                };
+
+                if let Entry::Vacant(vacant) = storage.profiles.entry(ulid) {
+                    report(
+                        ProblemKind::OrphanedProfileDirectory,
+                        format!("Profile directory {} does not belong to any profile", ulid),
+                        self.fix,
+                    );
+
+                    // Directories may contain user data, so they are recovered instead of removed
+                    if self.fix {
+                        let mut profile = Profile::new(
+                            Some("Recovered".into()),
+                            Some("Profile recovered from an existing directory".into()),
+                        );
+                        profile.ulid = ulid;
+                        vacant.insert(profile);
+                    }
+                }
+            }
+        }
+
+        // Check profiles and their patches
+        for profile in storage.profiles.values() {
+            let directory = dirs.userdata.join("profiles").join(profile.ulid.to_string());
+
+            let kind = if !directory.exists() {
+                ProblemKind::MissingProfileDirectory
+            } else if profile.needs_patch(&dirs) {
+                ProblemKind::OutdatedProfilePatch
+            } else {
+                continue;
+            };
+
+            let description = match kind {
+                ProblemKind::MissingProfileDirectory => "directory does not exist",
+                _ => "patch is outdated",
+            };
+
+            let fixed = self.fix && succeeded(profile.patch(&dirs));
+            report(kind, format!("Profile {} {}", profile.ulid, description), fixed);
+        }
+
+        // Fixes so far are written before the storage is unlocked for the remaining checks
+        if self.fix {
+            storage.write(&dirs)?;
+        }
+        let mut storage = storage.unlock();
+
+        // Check the runtime and its patch
+        let runtime = Runtime::new(&dirs)?;
+        if runtime.version.is_none() {
+            let fixed = self.fix && succeeded(RuntimeInstallCommand {}.run());
+            report(ProblemKind::MissingRuntime, "Runtime is not installed".into(), fixed);
+        } else if !runtime.is_patched() {
+            // Patching requires a web app on some systems, but it is also done on launch
+            let fixed = match storage.sites.values().next() {
+                Some(site) if self.fix => succeeded(runtime.patch(&dirs, site)),
+                _ => false,
+            };
+            report(ProblemKind::UnpatchedRuntime, "Runtime is not patched".into(), fixed);
+        }
+
+        // Check system integration of web apps
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        // Malformed web apps are repaired by downloading their manifest again
+        // The downloaded manifest still needs to be allowed by the policy
+        let policy = storage.policy.clone();
+        let repair = |site: &mut Site| -> Result<()> {
+            let mut candidate = site.clone();
+            candidate.repair(&client)?;
+            policy.check_site(&candidate.config, &candidate.manifest)?;
+            *site = candidate;
+            Ok(())
+        };
+
+        let mut repaired = vec![];
+        for site in storage.sites.values_mut() {
+            if let Err(error) = site.validate() {
+                let fixed = self.fix && succeeded(repair(site));
+                report(ProblemKind::MalformedSite, format!("{:#}", error), fixed);
+
+                if fixed {
+                    repaired.push(site.clone());
+                }
+            }
+        }
+
+        if !repaired.is_empty() {
+            let mut current = Storage::load(&dirs)?;
+            for site in repaired {
+                current.merge_site(site)?;
+            }
+            current.write(&dirs)?;
+        }
+
+        // System integration of malformed web apps cannot be verified
+        for site in storage.sites.values().filter(|site| site.validate().is_ok()) {
+            let missing = integrations::verify(&IntegrationVerifyArgs { site, dirs: &dirs })
+                .context("Failed to verify system integration")?;
+
+            if missing.is_empty() {
+                continue;
+            }
+
+            let fixed = self.fix
+                && succeeded(integrations::install(&IntegrationInstallArgs {
+                    site,
+                    dirs: &dirs,
+                    client: Some(&client),
+                    update_manifest: false,
+                    update_icons: true,
+                    old_name: None,
+                }));
+
+            report(
+                ProblemKind::MissingIntegration,
+                format!("Web app {} is missing {}", site.ulid, missing.join(", ")),
+                fixed,
+            );
+        }
+
+        // Check web apps from the policy that have not been preinstalled yet
+        // Web apps that were uninstalled by the user after they were preinstalled should stay uninstalled
+        let missing: Vec<_> = storage
+            .policy
+            .sites
+            .iter()
+            .filter(|policy| !storage.preinstalled.contains(&policy.manifest_url))
+            .filter(|policy| {
+                !storage.sites.values().any(|site| site.config.manifest_url == policy.manifest_url)
+            })
+            .collect();
+
+        let preinstalled = if self.fix
+            && !missing.is_empty()
+            && succeeded(preinstall_policy_sites(&dirs, &self.client))
+        {
+            Storage::load(&dirs)?.preinstalled
+        } else {
+            vec![]
+        };
+
+        for site in missing {
+            report(
+                ProblemKind::MissingPreinstalledSite,
+                format!("Web app {} from the policy is not installed", site.manifest_url),
+                preinstalled.contains(&site.manifest_url),
+            );
+        }
+
+        Ok(problems)
+    }
+}
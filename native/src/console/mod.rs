@@ -1,9 +1,21 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
 
 pub use crate::console::app::App;
-use crate::console::app::{ProfileCommand, RuntimeCommand, SiteCommand};
+use crate::console::app::{
+    ConfigBackupsCommand,
+    ConfigCommand,
+    ProfileCommand,
+    RuntimeCommand,
+    SiteCommand,
+    SiteManifestCommand,
+};
 
 pub mod app;
+pub mod apply;
+pub mod config;
+pub mod doctor;
 pub mod profile;
 pub mod runtime;
 pub mod site;
@@ -47,7 +59,52 @@ macro_rules! store_value_vec {
     };
 }
 
-pub(in crate::console) use {store_value, store_value_vec};
+/// Parses and stores `Option<Vec<X>>` parameters into non-optional lists.
+///
+/// Uses the same parsing hacks as [`store_value_vec`], but stores
+/// an empty list instead of `None`.
+///
+/// Rules:
+/// - `None` - Ignores the parameter and keeps its previous value.
+/// - `Some(vec![""])` -> Stores an empty list.
+/// - `Some(vec![a, b, c])` - Stores `vec![a, b, c]`.
+///
+macro_rules! store_value_list {
+    ($target:expr, $source:expr) => {
+        if let Some(source) = &$source {
+            if source.len() == 1 && source.first() == Some(&"".into()) {
+                $target = Default::default();
+            } else {
+                $target = source.to_vec();
+            }
+        }
+    };
+}
+
+pub(in crate::console) use store_value;
+pub(in crate::console) use store_value_list;
+pub(in crate::console) use store_value_vec;
+
+/// Parses environment variables in the `NAME=VALUE` format.
+///
+/// Empty strings are skipped, so a list with only an empty string
+/// results in no variables.
+pub(in crate::console) fn parse_variables(
+    variables: &[String],
+) -> Result<BTreeMap<String, String>> {
+    let mut parsed = BTreeMap::new();
+
+    for variable in variables.iter().filter(|variable| !variable.is_empty()) {
+        match variable.split_once('=') {
+            Some((name, value)) if !name.is_empty() => {
+                parsed.insert(name.into(), value.into());
+            }
+            _ => bail!("Invalid environment variable `{}`, expected `NAME=VALUE`", variable),
+        }
+    }
+
+    Ok(parsed)
+}
 
 pub trait Run {
     fn run(&self) -> Result<()>;
@@ -60,6 +117,9 @@ impl Run for App {
             App::Site(cmd) => cmd.run(),
             App::Profile(cmd) => cmd.run(),
             App::Runtime(cmd) => cmd.run(),
+            App::Config(cmd) => cmd.run(),
+            App::Doctor(cmd) => cmd.run(),
+            App::Apply(cmd) => cmd.run(),
         }
     }
 }
@@ -68,10 +128,30 @@ impl Run for SiteCommand {
     #[inline]
     fn run(&self) -> Result<()> {
         match self {
+            SiteCommand::List(cmd) => cmd.run(),
             SiteCommand::Launch(cmd) => cmd.run(),
             SiteCommand::Install(cmd) => cmd.run(),
             SiteCommand::Uninstall(cmd) => cmd.run(),
+            SiteCommand::Prune(cmd) => cmd.run(),
             SiteCommand::Update(cmd) => cmd.run(),
+            SiteCommand::Move(cmd) => cmd.run(),
+            SiteCommand::Clone(cmd) => cmd.run(),
+            SiteCommand::Check(cmd) => cmd.run(),
+            SiteCommand::Manifest(cmd) => cmd.run(),
+            SiteCommand::Export(cmd) => cmd.run(),
+            SiteCommand::Import(cmd) => cmd.run(),
+        }
+    }
+}
+
+impl Run for SiteManifestCommand {
+    #[inline]
+    fn run(&self) -> Result<()> {
+        match self {
+            SiteManifestCommand::History(cmd) => cmd.run(),
+            SiteManifestCommand::Pin(cmd) => cmd.run(),
+            SiteManifestCommand::Unpin(cmd) => cmd.run(),
+            SiteManifestCommand::Rollback(cmd) => cmd.run(),
         }
     }
 }
@@ -97,3 +177,22 @@ impl Run for RuntimeCommand {
         }
     }
 }
+
+impl Run for ConfigCommand {
+    #[inline]
+    fn run(&self) -> Result<()> {
+        match self {
+            ConfigCommand::Backups(cmd) => cmd.run(),
+            ConfigCommand::Restore(cmd) => cmd.run(),
+        }
+    }
+}
+
+impl Run for ConfigBackupsCommand {
+    #[inline]
+    fn run(&self) -> Result<()> {
+        match self {
+            ConfigBackupsCommand::List(cmd) => cmd.run(),
+        }
+    }
+}
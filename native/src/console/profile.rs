@@ -1,9 +1,8 @@
-use std::fs::{create_dir_all, remove_dir_all};
+use std::fs::remove_dir_all;
 use std::io;
 use std::io::Write;
 
 use anyhow::{Context, Result};
-use fs_extra::dir::{copy, CopyOptions};
 use log::{info, warn};
 use ulid::Ulid;
 
@@ -14,7 +13,7 @@ use crate::console::app::{
     ProfileRemoveCommand,
     ProfileUpdateCommand,
 };
-use crate::console::{store_value, Run};
+use crate::console::{parse_variables, store_value, store_value_list, Run};
 use crate::directories::ProjectDirs;
 use crate::integrations;
 use crate::integrations::IntegrationUninstallArgs;
@@ -33,12 +32,27 @@ impl Run for ProfileListCommand {
                 profile.ulid
             );
 
+            if !profile.arguments.is_empty() {
+                println!("Arguments: {}", profile.arguments.join(" "));
+            }
+
+            for (name, value) in &profile.variables {
+                println!("Variable: {}={}", name, value);
+            }
+
             if !profile.sites.is_empty() {
                 println!("\nApps:");
             }
 
-            for site in profile.sites {
-                let site = storage.sites.get(&site).context("Profile with invalid web app")?;
+            for id in profile.sites {
+                // Web apps whose files are missing should not prevent listing other web apps
+                let site = match storage.sites.get(&id) {
+                    Some(site) => site,
+                    None => {
+                        warn!("Profile lists non-existing web app {}, skipping it", id);
+                        continue;
+                    }
+                };
 
                 let url = if site.config.manifest_url.scheme() != "data" {
                     &site.config.manifest_url
@@ -73,19 +87,10 @@ impl ProfileCreateCommand {
         let profile = Profile::new(self.name.clone(), self.description.clone());
         let ulid = profile.ulid;
 
-        storage.profiles.insert(ulid, profile);
+        storage.profiles.insert(ulid, profile.clone());
         storage.write(&dirs)?;
 
-        if let Some(template) = &self.template {
-            let mut options = CopyOptions::new();
-            options.content_only = true;
-            options.overwrite = true;
-
-            info!("Copying a profile template");
-            let target = dirs.userdata.join("profiles").join(ulid.to_string());
-            create_dir_all(&target).context("Failed to create a profile directory")?;
-            copy(template, target, &options).context("Failed to copy a profile template")?;
-        }
+        profile.create_directory(&dirs, self.template.as_deref())?;
 
         info!("Profile created: {}", ulid);
         Ok(ulid)
@@ -95,9 +100,10 @@ impl ProfileCreateCommand {
 impl Run for ProfileRemoveCommand {
     fn run(&self) -> Result<()> {
         let dirs = ProjectDirs::new()?;
-        let mut storage = Storage::load(&dirs)?;
 
-        let profile = storage.profiles.get_mut(&self.id).context("Profile does not exist")?;
+        // The storage is not locked while waiting for the confirmation
+        let storage = Storage::load(&dirs)?.unlock();
+        storage.profiles.get(&self.id).context("Profile does not exist")?;
 
         if !self.quiet {
             warn!("This will completely remove the profile and all associated web apps, including their data");
@@ -116,6 +122,9 @@ impl Run for ProfileRemoveCommand {
             }
         }
 
+        let mut storage = Storage::load(&dirs)?;
+        let profile = storage.profiles.get_mut(&self.id).context("Profile does not exist")?;
+
         if profile.ulid == Ulid::nil() {
             warn!("Default profile cannot be completely removed");
             warn!("Web apps and data will be cleared, but the profile will stay");
@@ -125,10 +134,14 @@ impl Run for ProfileRemoveCommand {
         let _ = remove_dir_all(dirs.userdata.join("profiles").join(self.id.to_string()));
 
         info!("Removing web apps");
-        for site in &profile.sites {
-            if let Some(site) = storage.sites.remove(site) {
-                integrations::uninstall(&IntegrationUninstallArgs { site: &site, dirs: &dirs })
-                    .context("Failed to uninstall system integration")?;
+        let mut missing = vec![];
+        for id in &profile.sites {
+            match storage.sites.remove(id) {
+                Some(site) => {
+                    integrations::uninstall(&IntegrationUninstallArgs { site: &site, dirs: &dirs })
+                        .context("Failed to uninstall system integration")?
+                }
+                None => missing.push(*id),
             }
         }
 
@@ -139,6 +152,11 @@ impl Run for ProfileRemoveCommand {
             profile.sites.clear();
         }
 
+        // Web apps whose files are missing also need to be removed from the storage index
+        for id in missing {
+            storage.forget_missing_site(&id);
+        }
+
         storage.write(&dirs)?;
 
         info!("Profile removed!");
@@ -156,6 +174,12 @@ impl Run for ProfileUpdateCommand {
         info!("Updating the profile");
         store_value!(profile.name, self.name);
         store_value!(profile.description, self.description);
+        store_value_list!(profile.arguments, self.arguments);
+
+        if let Some(variables) = &self.variables {
+            profile.variables = parse_variables(variables)?;
+        }
+
         storage.write(&dirs)?;
 
         info!("Profile updated!");
@@ -1,42 +1,191 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
-use std::fs::metadata;
+use std::env::current_dir;
+use std::fs::{create_dir_all, read_dir, remove_file, File, OpenOptions};
 use std::io;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context, Result};
 use cfg_if::cfg_if;
 use log::{info, warn};
+use reqwest::blocking::Client;
+use serde_json::{from_str, Value};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use ulid::Ulid;
 use url::Url;
 
+use crate::components::bundle::Bundle;
+use crate::components::check::ManifestProblem;
+use crate::components::discovery::Discovery;
+use crate::components::instance::Instance;
 use crate::components::runtime::Runtime;
-use crate::components::site::{Site, SiteConfig};
+use crate::components::site::{
+    ManifestCache,
+    ManifestChange,
+    ShareData,
+    ShareMethod,
+    Site,
+    SiteConfig,
+    SiteUsage,
+};
 use crate::console::app::{
+    DuplicateMode,
+    HTTPClientConfig,
+    SiteCheckCommand,
+    SiteCloneCommand,
+    SiteExportCommand,
+    SiteImportCommand,
     SiteInstallCommand,
     SiteLaunchCommand,
+    SiteListCommand,
+    SiteManifestHistoryCommand,
+    SiteManifestPinCommand,
+    SiteManifestRollbackCommand,
+    SiteManifestUnpinCommand,
+    SiteMoveCommand,
+    SitePruneCommand,
     SiteUninstallCommand,
     SiteUpdateCommand,
 };
-use crate::console::{store_value, store_value_vec, Run};
+use crate::console::{parse_variables, store_value, store_value_list, store_value_vec, Run};
 use crate::directories::ProjectDirs;
 use crate::integrations;
 use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
-use crate::storage::Storage;
+use crate::storage::policy::{Policy, PolicySite};
+use crate::storage::{Config, Storage};
 use crate::utils::construct_certificates_and_client;
 
+impl Run for SiteListCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let storage = Storage::load(&dirs)?.unlock();
+
+        if storage.sites.is_empty() {
+            info!("No web apps installed");
+            return Ok(());
+        }
+
+        // Recently used web apps are listed first, and web apps that were never launched last
+        let mut sites: Vec<_> = storage.sites.values().collect();
+        sites.sort_by_key(|site| Reverse(site.usage.last_launched));
+
+        for site in sites {
+            let usage = &site.usage;
+
+            match usage.last_launched {
+                Some(launched) => print!(
+                    "- {}: {} launches, last at {}",
+                    site.name(),
+                    usage.launches,
+                    format_timestamp(launched)
+                ),
+                None => print!("- {}: never launched", site.name()),
+            }
+
+            if usage.running_time > 0 {
+                let minutes = usage.running_time / 60;
+                print!(", running for {}h {}m", minutes / 60, minutes % 60);
+            }
+
+            println!(" ({})", site.ulid);
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a time in milliseconds since the Unix epoch as RFC 3339.
+///
+/// Usage statistics could contain invalid times, which should not prevent
+/// listing other web apps, so they are formatted as an unknown time instead.
+fn format_timestamp(timestamp: u64) -> String {
+    OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128 * 1_000_000)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_else(|| "unknown time".into())
+}
+
+/// How long share forms are kept before they are removed.
+///
+/// Forms are loaded by the runtime after it is launched, so they cannot be
+/// removed immediately. Instead, old forms are removed on the next share.
+const SHARE_FORM_LIFETIME: Duration = Duration::from_secs(60);
+
+/// Writes the share form into a private directory and returns its URL.
+///
+/// The form contains shared data, so it is only readable by the current user.
+fn write_share_form(dirs: &ProjectDirs, document: &str) -> Result<Url> {
+    const SHARE_FORM_ERROR: &str = "Failed to write share form";
+
+    let directory = dirs.userdata.join("share");
+    create_dir_all(&directory).context(SHARE_FORM_ERROR)?;
+
+    #[cfg(unix)]
+    {
+        use std::fs::{set_permissions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+        set_permissions(&directory, Permissions::from_mode(0o700)).context(SHARE_FORM_ERROR)?;
+    }
+
+    for entry in read_dir(&directory).context(SHARE_FORM_ERROR)?.flatten() {
+        let path = entry.path();
+        let created = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| Ulid::from_string(stem).ok())
+            .map(|id| id.datetime());
+
+        if let Some(Ok(age)) = created.map(|created| created.elapsed()) {
+            if age > SHARE_FORM_LIFETIME {
+                let _ = remove_file(&path);
+            }
+        }
+    }
+
+    let filename = directory.join(format!("{}.html", Ulid::new()));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&filename).context(SHARE_FORM_ERROR)?;
+    file.write_all(document.as_bytes()).context(SHARE_FORM_ERROR)?;
+
+    Url::from_file_path(&filename).ok().context("Failed to convert share form path")
+}
+
 impl Run for SiteLaunchCommand {
     fn run(&self) -> Result<()> {
         let dirs = ProjectDirs::new()?;
-        let storage = Storage::load(&dirs)?;
+
+        // Launching does not modify the storage, and the runtime may keep running for a long time
+        // The storage lock therefore needs to be released so other processes can still use it
+        // Only the launched web app and its profile are loaded, so launching stays fast
+        let storage = Storage::load_site(&dirs, &self.id)?.unlock();
 
         let site = storage.sites.get(&self.id).context("Web app does not exist")?;
         let args = if !&self.arguments.is_empty() { &self.arguments } else { &storage.arguments };
 
+        // Custom URLs could otherwise open any website inside the web app profile
+        if let Some(url) = &self.url {
+            if !check_url_scope(site, url, &storage.config)? {
+                return Ok(());
+            }
+        }
+
         cfg_if! {
             if #[cfg(target_os = "macos")] {
                 use crate::integrations;
 
-                if !self.direct_launch {
+                // File handlers and share targets are not registered on macOS, so they are always launched directly
+                if !self.direct_launch && self.file.is_empty() && self.share.is_empty() {
                     integrations::launch(site, &self.url, args)?;
                     return Ok(())
                 }
@@ -50,31 +199,20 @@ impl Run for SiteLaunchCommand {
             bail!("Runtime not installed");
         }
 
+        // The runtime passes the launch to a running instance of the profile
+        // Its runtime and profile are in use, so they cannot be patched
+        let instance = Instance::load(&dirs, &site.profile);
+
         // Patching on macOS is always needed to correctly show the web app name
         // Otherwise, patch runtime and profile only if needed
-        let should_patch = if cfg!(target_os = "macos") || storage.config.always_patch {
+        let should_patch = if instance.is_some() {
+            info!("Profile is already running, skipping patching");
+            false
+        } else if cfg!(target_os = "macos") || storage.config.always_patch {
             // Force patching if this is enabled
             true
         } else {
-            // Uses "chrome.jsm" file because it contains version info
-            let source = dirs.sysdata.join("userchrome/profile/chrome/pwa/chrome.jsm");
-            let target = dirs
-                .userdata
-                .join("profiles")
-                .join(profile.ulid.to_string())
-                .join("chrome/pwa/chrome.jsm");
-
-            // Only patch if modification dates of source and target are different
-            // In case any error happens, just force patching
-            if let (Ok(source), Ok(target)) = (metadata(source), metadata(target)) {
-                if let (Ok(source), Ok(target)) = (source.modified(), target.modified()) {
-                    source > target
-                } else {
-                    true
-                }
-            } else {
-                true
-            }
+            profile.needs_patch(&dirs)
         };
 
         if should_patch {
@@ -110,19 +248,106 @@ impl Run for SiteLaunchCommand {
                 .context("Failed to convert protocol handler")?;
             let handler = handler.replacen("%s", &input, 1);
             let handler = Url::parse(&handler).context("Failed to convert protocol handler")?;
+
+            // Custom protocol handlers are not restricted to the web app scope
+            if !check_url_scope(site, &handler, &storage.config)? {
+                return Ok(());
+            }
+
             Some(handler)
+        } else if !self.file.is_empty() {
+            // Handle files with the file handler action URL
+            // The files themselves cannot be passed to the web app yet, see the runtime boot script
+            for file in &self.file {
+                if !file.is_file() {
+                    bail!("File {} does not exist", file.display());
+                }
+            }
+
+            let handler = site
+                .file_handler(&self.file)
+                .context("No enabled file handler accepts the files")?;
+            Some(handler.action)
+        } else if !self.share.is_empty() {
+            // Handle shared data with the share target
+            // See: https://w3c.github.io/web-share-target/#share-target-procedure
+            let target =
+                site.share_target().context("Web app does not have a valid share target")?;
+            let data = parse_share_data(&self.share)?;
+
+            match target.method {
+                ShareMethod::Get => Some(target.query_url(&data)),
+                ShareMethod::Post => {
+                    // The runtime can only send a POST request by submitting a form
+                    Some(write_share_form(&dirs, &target.form_document(&data))?)
+                }
+            }
         } else {
             None
         };
 
         let url = if handler.is_some() { &handler } else { &self.url };
 
-        info!("Launching the web app");
-        cfg_if! {
-            if #[cfg(target_os = "macos")] {
-                site.launch(&dirs, &runtime, &storage.config, url, args, storage.variables)?.wait()?;
-            } else {
-                site.launch(&dirs, &runtime, &storage.config, url, args, storage.variables)?;
+        // The runtime has its own working directory, so it needs absolute file paths
+        let directory = current_dir().context("Failed to get the working directory")?;
+        let files: Vec<_> = self.file.iter().map(|file| directory.join(file)).collect();
+
+        // Policy arguments and variables are included before the user ones
+        let args = &[storage.policy.arguments.as_slice(), args].concat();
+        let vars = storage.policy.variables.clone().into_iter().chain(storage.variables.clone());
+
+        let forwarded = instance.is_some();
+        match &instance {
+            Some(instance) if instance.sites.contains(&site.ulid) => {
+                info!("Web app is already running, passing the launch to it")
+            }
+            Some(_) => info!("Launching the web app in the running instance"),
+            None => info!("Launching the web app"),
+        }
+
+        #[allow(unused_mut)]
+        let mut child = site.launch(
+            &dirs,
+            &runtime,
+            &storage.config,
+            profile,
+            url,
+            &files,
+            forwarded,
+            args,
+            vars,
+        )?;
+
+        // Forwarded launches exit immediately, so the running instance is kept
+        let result = if forwarded {
+            Instance::add_site(&dirs, &site.profile, &site.ulid)
+        } else {
+            let instance = Instance { pid: child.id(), sites: [site.ulid].into() };
+            instance.write(&dirs, &site.profile)
+        };
+        if let Err(error) = result {
+            warn!("{:?}", error.context("Failed to store the running instance"));
+        }
+
+        // Usage statistics are not essential, so failing to record them does not fail the launch
+        if let Err(error) = Storage::update_usage(&dirs, &site.ulid, SiteUsage::record_launch) {
+            warn!("{:?}", error.context("Failed to record the web app launch"));
+        }
+
+        // Forwarded launches exit immediately, so only the runtime that was started is timed
+        #[cfg(target_os = "macos")]
+        {
+            use std::time::Instant;
+
+            let started = Instant::now();
+            child.wait()?;
+
+            if !forwarded {
+                let elapsed = started.elapsed().as_secs();
+                let update = |usage: &mut SiteUsage| usage.running_time += elapsed;
+                if let Err(error) = Storage::update_usage(&dirs, &site.ulid, update) {
+                    warn!("{:?}", error.context("Failed to record the web app running time"));
+                }
             }
         }
 
@@ -130,25 +355,135 @@ impl Run for SiteLaunchCommand {
     }
 }
 
+/// Checks whether the URL may be opened in the web app.
+///
+/// URLs outside the web app scope and its enabled URL handlers are rejected,
+/// or opened in the default browser if this is enabled in the config. Returns
+/// `false` if the URL was opened in the default browser instead.
+fn check_url_scope(site: &Site, url: &Url, config: &Config) -> Result<bool> {
+    if site.allows_url(url) {
+        return Ok(true);
+    }
+
+    if !config.open_out_of_scope_urls {
+        bail!("URL {} is not within the web app scope", url);
+    }
+
+    info!("URL is not within the web app scope, opening it in the default browser");
+    integrations::open(url)?;
+    Ok(false)
+}
+
+/// Parses shared data specified as `title=...,text=...,url=...`.
+///
+/// Values can contain commas, so a value only ends before
+/// a comma that is followed by another known field name.
+fn parse_share_data(values: &[String]) -> Result<ShareData> {
+    const FIELDS: [&str; 3] = ["title=", "text=", "url="];
+    let mut data = ShareData::default();
+
+    for value in values {
+        let mut rest = value.as_str();
+
+        while !rest.is_empty() {
+            let (name, tail) = rest.split_once('=').context("Invalid share data format")?;
+            let end = tail
+                .match_indices(',')
+                .map(|(index, _)| index)
+                .find(|index| FIELDS.iter().any(|field| tail[index + 1..].starts_with(field)))
+                .unwrap_or(tail.len());
+
+            let field = match name {
+                "title" => &mut data.title,
+                "text" => &mut data.text,
+                "url" => &mut data.url,
+                _ => bail!("Unknown share data field: {}", name),
+            };
+
+            *field = Some(tail[..end].into());
+            rest = tail.get(end + 1..).unwrap_or_default();
+        }
+    }
+
+    Ok(data)
+}
+
 impl Run for SiteInstallCommand {
     fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        if let Err(error) = preinstall_policy_sites(&dirs, &self.client) {
+            warn!("Failed to preinstall web apps from the policy: {:#}", error);
+        }
+
         self._run()?;
         Ok(())
     }
 }
 
 impl SiteInstallCommand {
-    pub fn _run(&self) -> Result<Ulid> {
-        if self.manifest_url.scheme() == "data" && self.document_url.is_none() {
-            bail!("The document URL is required when the manifest URL is a data URL");
+    /// Creates a command that installs the web app from the policy.
+    ///
+    /// Web apps that are already installed in the default profile are updated.
+    pub fn from_policy(site: &PolicySite, client: HTTPClientConfig) -> Self {
+        Self {
+            manifest_url: Some(site.manifest_url.clone()),
+            document_url: site.document_url.clone(),
+            page: None,
+            synthesize_manifest: false,
+            duplicate: DuplicateMode::Update,
+            profile: None,
+            start_url: None,
+            icon_url: None,
+            name: None,
+            description: None,
+            categories: None,
+            keywords: None,
+            launch_on_login: None,
+            launch_on_browser: None,
+            system_integration: true,
+            client,
         }
+    }
+
+    pub fn _run(&self) -> Result<Ulid> {
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        let (manifest_url, document_url) = match (&self.page, &self.manifest_url) {
+            (Some(page), _) => {
+                let discovery = Discovery::new(page, &client, self.synthesize_manifest)?;
+                (discovery.manifest_url, discovery.document_url)
+            }
+            (None, Some(manifest_url)) => {
+                let document_url = match &self.document_url {
+                    Some(url) => url.clone(),
+                    None if manifest_url.scheme() == "data" => {
+                        bail!("The document URL is required when the manifest URL is a data URL")
+                    }
+                    None => manifest_url.join(".")?,
+                };
+                (manifest_url.clone(), document_url)
+            }
+            (None, None) => bail!("Either the manifest URL or the page URL is required"),
+        };
 
         let dirs = ProjectDirs::new()?;
-        let mut storage = Storage::load(&dirs)?;
+
+        let storage = Storage::load(&dirs)?.unlock();
+
+        // Data URLs do not have an origin, so their manifest is checked once it is processed
+        if manifest_url.scheme() != "data" {
+            storage.policy.check_url(&manifest_url)?;
+        }
+        storage.policy.check_url(&document_url)?;
 
         let profile = storage
             .profiles
-            .get_mut(&self.profile.unwrap_or_else(Ulid::nil))
+            .get(&self.profile.unwrap_or_else(Ulid::nil))
             .context("Profile does not exist")?;
 
         info!("Installing the web app");
@@ -158,30 +493,46 @@ impl SiteInstallCommand {
             description: self.description.clone(),
             categories: self.categories.clone(),
             keywords: self.keywords.clone(),
-            document_url: match &self.document_url {
-                Some(url) => url.clone(),
-                None => self.manifest_url.join(".")?,
-            },
-            manifest_url: self.manifest_url.clone(),
+            locale: None,
+            document_url,
+            manifest_url,
+            key: None,
             start_url: self.start_url.clone(),
             icon_url: self.icon_url.clone(),
             enabled_url_handlers: vec![],
             enabled_protocol_handlers: vec![],
             custom_protocol_handlers: vec![],
+            enabled_file_handlers: vec![],
             launch_on_login: self.launch_on_login.unwrap_or(false),
             launch_on_browser: self.launch_on_browser.unwrap_or(false),
+            arguments: vec![],
+            variables: BTreeMap::new(),
+            runtime_enable_wayland: None,
+            runtime_use_xinput2: None,
+            runtime_use_portals: None,
         };
 
-        let client = construct_certificates_and_client(
-            &self.client.tls_root_certificates_der,
-            &self.client.tls_root_certificates_pem,
-            self.client.tls_danger_accept_invalid_certs,
-            self.client.tls_danger_accept_invalid_hostnames,
-        )?;
-
-        let site = Site::new(profile.ulid, config, &client)?;
+        let mut site = Site::new(profile.ulid, config, &client)?;
         let ulid = site.ulid;
 
+        // The manifest may point to other origins than the URLs it was installed from
+        storage.policy.check_site(&site.config, &site.manifest)?;
+
+        if let Some(existing) = site.find_duplicate(storage.sites.values(), &site.profile) {
+            match self.duplicate {
+                DuplicateMode::Refuse => bail!(
+                    "Web app is already installed in this profile as {}, update it or allow duplicates",
+                    existing
+                ),
+                DuplicateMode::Update => {
+                    return self.update_duplicate(&storage, &dirs, &client, existing, site);
+                }
+                DuplicateMode::Allow => {
+                    warn!("Web app is already installed in this profile as {}", existing)
+                }
+            }
+        }
+
         if self.system_integration {
             info!("Installing system integration");
             integrations::install(&IntegrationInstallArgs {
@@ -193,23 +544,153 @@ impl SiteInstallCommand {
                 old_name: None,
             })
             .context("Failed to install system integration")?;
+
+            site.failed_icons = integrations::take_icon_failures();
         }
 
-        profile.sites.push(ulid);
-        storage.sites.insert(ulid, site);
+        let mut storage = Storage::load(&dirs)?;
+        storage.insert_site(site)?;
         storage.write(&dirs)?;
 
         info!("Web app installed: {}", ulid);
         Ok(ulid)
     }
+
+    /// Updates the existing web app with the newly installed one.
+    ///
+    /// The existing web app keeps its ID, settings and manifest history, and only
+    /// gets the new URLs and the values that were set for this installation. The new
+    /// manifest is applied the same way as when updating the web app, so sensitive
+    /// changes stay pending and pinned manifests are not changed.
+    fn update_duplicate(
+        &self,
+        storage: &Storage,
+        dirs: &ProjectDirs,
+        client: &Client,
+        ulid: Ulid,
+        site: Site,
+    ) -> Result<Ulid> {
+        info!("Updating the existing web app {}", ulid);
+
+        let mut existing = storage.sites.get(&ulid).context("Web app does not exist")?.clone();
+        let old_name = existing.name();
+        self.merge_duplicate_config(&mut existing.config, &site.config);
+
+        if existing.pinned {
+            info!("Web app manifest is pinned, keeping the existing manifest");
+        } else {
+            let changes = existing.apply_manifest(site.cache, false)?;
+            log_manifest_changes(&existing, &changes);
+        }
+
+        if self.system_integration {
+            info!("Updating system integration");
+            integrations::install(&IntegrationInstallArgs {
+                site: &existing,
+                dirs,
+                client: Some(client),
+                update_manifest: true,
+                update_icons: true,
+                old_name: Some(&old_name),
+            })
+            .context("Failed to update system integration")?;
+
+            existing.failed_icons = integrations::take_icon_failures();
+        }
+
+        // Config changes are applied again, so other changes made in the meantime are kept
+        let mut storage = Storage::load(dirs)?;
+        self.merge_duplicate_config(&mut storage.merge_site(existing)?.config, &site.config);
+        storage.write(dirs)?;
+
+        info!("Web app updated: {}", ulid);
+        Ok(ulid)
+    }
+
+    /// Merges the config of the newly installed web app into the existing one.
+    fn merge_duplicate_config(&self, config: &mut SiteConfig, new: &SiteConfig) {
+        config.document_url = new.document_url.clone();
+        config.manifest_url = new.manifest_url.clone();
+        config.name = new.name.clone().or(config.name.take());
+        config.description = new.description.clone().or(config.description.take());
+        config.categories = new.categories.clone().or(config.categories.take());
+        config.keywords = new.keywords.clone().or(config.keywords.take());
+        config.start_url = new.start_url.clone().or(config.start_url.take());
+        config.icon_url = new.icon_url.clone().or(config.icon_url.take());
+        store_value!(config.launch_on_login, self.launch_on_login);
+        store_value!(config.launch_on_browser, self.launch_on_browser);
+    }
+}
+
+/// Prints manifest changes and warns about changes that need to be accepted.
+fn log_manifest_changes(site: &Site, changes: &[ManifestChange]) {
+    for change in changes {
+        if change.sensitive && site.pending_manifest.is_some() {
+            warn!("Manifest change needs to be accepted: {}", change);
+        } else {
+            info!("Manifest change: {}", change);
+        }
+    }
+
+    if site.pending_manifest.is_some() {
+        warn!("Some manifest changes are pending, accept them with --accept-manifest-changes");
+    }
+}
+
+/// Installs web apps from the policy that have not been preinstalled yet.
+///
+/// Each web app is only preinstalled once, so users can still uninstall it.
+/// Web apps that the user has already installed are only marked as preinstalled.
+/// Web apps that fail to install are skipped and retried the next time.
+///
+/// Preinstalling may download many manifests and icons, so it is only done when
+/// installing web apps, updating all web apps, or fixing the consistency problems.
+pub fn preinstall_policy_sites(dirs: &ProjectDirs, client: &HTTPClientConfig) -> Result<()> {
+    // Most systems do not have any preinstalled web apps, so the storage is not loaded for them
+    let policy = Policy::load(dirs)?;
+    if policy.sites.is_empty() {
+        return Ok(());
+    }
+
+    // Installing web apps locks the storage by itself
+    let storage = Storage::load(dirs)?.unlock();
+    let mut preinstalled = vec![];
+
+    for site in
+        policy.sites.iter().filter(|site| !storage.preinstalled.contains(&site.manifest_url))
+    {
+        if storage
+            .sites
+            .values()
+            .any(|installed| installed.config.manifest_url == site.manifest_url)
+        {
+            preinstalled.push(site.manifest_url.clone());
+            continue;
+        }
+
+        info!("Preinstalling web app {} from the policy", site.manifest_url);
+        match SiteInstallCommand::from_policy(site, client.clone())._run() {
+            Ok(_) => preinstalled.push(site.manifest_url.clone()),
+            Err(error) => warn!("Failed to preinstall web app {}: {:#}", site.manifest_url, error),
+        }
+    }
+
+    if preinstalled.is_empty() {
+        return Ok(());
+    }
+
+    let mut storage = Storage::load(dirs)?;
+    storage.preinstalled.extend(preinstalled);
+    storage.write(dirs)
 }
 
 impl Run for SiteUninstallCommand {
     fn run(&self) -> Result<()> {
         let dirs = ProjectDirs::new()?;
-        let mut storage = Storage::load(&dirs)?;
 
-        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+        // The storage is not locked while waiting for the confirmation
+        let storage = Storage::load(&dirs)?.unlock();
+        storage.sites.get(&self.id).context("Web app does not exist")?;
 
         if !self.quiet {
             warn!("This will remove the web app");
@@ -228,6 +709,9 @@ impl Run for SiteUninstallCommand {
             }
         }
 
+        let mut storage = Storage::load(&dirs)?;
+        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+
         info!("Uninstalling the web app");
         storage
             .profiles
@@ -252,25 +736,102 @@ impl Run for SiteUninstallCommand {
     }
 }
 
+impl Run for SitePruneCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+
+        // The storage is not locked while waiting for the confirmation
+        let storage = Storage::load(&dirs)?.unlock();
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let threshold = now.saturating_sub(self.unused_for).as_millis() as u64;
+
+        let unused: Vec<_> = storage
+            .sites
+            .values()
+            .filter(|site| site.last_used() < threshold)
+            .map(|site| site.ulid)
+            .collect();
+
+        if unused.is_empty() {
+            info!("No unused web apps found");
+            return Ok(());
+        }
+
+        for id in &unused {
+            let site = storage.sites.get(id).context("Web app does not exist")?;
+            match site.usage.last_launched {
+                Some(launched) => {
+                    println!(
+                        "- {}: last used at {} ({})",
+                        site.name(),
+                        format_timestamp(launched),
+                        id
+                    )
+                }
+                None => println!(
+                    "- {}: never launched since {} ({})",
+                    site.name(),
+                    format_timestamp(site.ulid.timestamp_ms()),
+                    id
+                ),
+            }
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        if !self.quiet {
+            warn!("This will remove {} unused web apps", unused.len());
+            warn!("Data will NOT be removed, remove them from the app browser");
+
+            print!("Do you want to continue (y/n)? ");
+            io::stdout().flush()?;
+
+            let mut confirm = String::new();
+            io::stdin().read_line(&mut confirm)?;
+            confirm = confirm.trim().into();
+
+            if confirm != "Y" && confirm != "y" {
+                info!("Aborting!");
+                return Ok(());
+            }
+        }
+
+        info!("Uninstalling unused web apps");
+        for id in unused {
+            let system_integration = self.system_integration;
+            let command = SiteUninstallCommand { id, quiet: true, system_integration };
+            command.run()?;
+        }
+
+        info!("Unused web apps uninstalled!");
+        Ok(())
+    }
+}
+
 impl Run for SiteUpdateCommand {
+    #[inline]
     fn run(&self) -> Result<()> {
+        self._run()?;
+        Ok(())
+    }
+}
+
+impl SiteUpdateCommand {
+    pub fn _run(&self) -> Result<Vec<ManifestChange>> {
         let dirs = ProjectDirs::new()?;
-        let mut storage = Storage::load(&dirs)?;
 
-        let site = storage.sites.get_mut(&self.id).context("Web app does not exist")?;
+        let storage = Storage::load_site(&dirs, &self.id)?.unlock();
+
+        let mut site = storage.sites.get(&self.id).context("Web app does not exist")?.clone();
         let old_name = site.name();
+        let old_config = site.config.clone();
+        let old_manifest = site.manifest.clone();
 
         info!("Updating the web app");
-        store_value!(site.config.name, self.name);
-        store_value!(site.config.description, self.description);
-        store_value!(site.config.start_url, self.start_url);
-        store_value!(site.config.icon_url, self.icon_url);
-        store_value_vec!(site.config.categories, self.categories);
-        store_value_vec!(site.config.keywords, self.keywords);
-        store_value!(site.config.enabled_url_handlers, self.enabled_url_handlers);
-        store_value!(site.config.enabled_protocol_handlers, self.enabled_protocol_handlers);
-        store_value!(site.config.launch_on_login, self.launch_on_login);
-        store_value!(site.config.launch_on_browser, self.launch_on_browser);
+        self.apply_config(&mut site.config)?;
 
         let client = construct_certificates_and_client(
             &self.client.tls_root_certificates_der,
@@ -279,26 +840,430 @@ impl Run for SiteUpdateCommand {
             self.client.tls_danger_accept_invalid_hostnames,
         )?;
 
+        if self.accept_manifest_changes && site.accept_manifest_changes() {
+            info!("Accepted pending manifest changes");
+        }
+        if self.reject_manifest_changes && site.reject_manifest_changes() {
+            info!("Rejected pending manifest changes");
+        }
+
+        let mut changes = vec![];
         if self.update_manifest {
-            site.update(&client).context("Failed to update web app manifest")?;
+            changes = site
+                .update(&client, self.accept_manifest_changes)
+                .context("Failed to update web app manifest")?;
         }
+        log_manifest_changes(&site, &changes);
+
+        storage.policy.check_site(&site.config, &site.manifest)?;
+
+        // Icons only need to be regenerated if the manifest or config has changed
+        // Icons that have failed before are also retried
+        let changed = !self.update_manifest
+            || site.manifest != old_manifest
+            || site.config != old_config
+            || site.failed_icons;
 
         if self.system_integration {
             info!("Updating system integration");
             integrations::install(&IntegrationInstallArgs {
-                site,
+                site: &site,
                 dirs: &dirs,
                 client: Some(&client),
                 update_manifest: self.update_manifest,
-                update_icons: self.update_icons,
+                update_icons: self.update_icons && changed,
                 old_name: Some(&old_name),
             })
             .context("Failed to update system integration")?;
+
+            if self.update_icons && changed {
+                site.failed_icons = integrations::take_icon_failures();
+            }
         }
 
+        // Config changes are applied again, so other changes made in the meantime are kept
+        let mut storage = Storage::load(&dirs)?;
+        self.apply_config(&mut storage.merge_site(site)?.config)?;
         storage.write(&dirs)?;
 
         info!("Web app updated!");
+        Ok(changes)
+    }
+
+    /// Applies config values that were set for this update.
+    fn apply_config(&self, config: &mut SiteConfig) -> Result<()> {
+        store_value!(config.name, self.name);
+        store_value!(config.description, self.description);
+        store_value!(config.start_url, self.start_url);
+        store_value!(config.icon_url, self.icon_url);
+        store_value_vec!(config.categories, self.categories);
+        store_value_vec!(config.keywords, self.keywords);
+        store_value!(config.locale, self.locale);
+        store_value!(config.enabled_url_handlers, self.enabled_url_handlers);
+        store_value!(config.enabled_protocol_handlers, self.enabled_protocol_handlers);
+        store_value!(config.enabled_file_handlers, self.enabled_file_handlers);
+        store_value!(config.launch_on_login, self.launch_on_login);
+        store_value!(config.launch_on_browser, self.launch_on_browser);
+        store_value_list!(config.arguments, self.arguments);
+        store_value!(config.runtime_enable_wayland, self.runtime_enable_wayland);
+        store_value!(config.runtime_use_xinput2, self.runtime_use_xinput2);
+        store_value!(config.runtime_use_portals, self.runtime_use_portals);
+
+        if let Some(variables) = &self.variables {
+            config.variables = parse_variables(variables)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Run for SiteMoveCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let mut storage = Storage::load(&dirs)?;
+
+        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+        let old_profile = site.profile;
+
+        if !storage.profiles.contains_key(&self.profile) {
+            bail!("Profile does not exist");
+        }
+
+        if old_profile == self.profile {
+            bail!("Web app is already in this profile");
+        }
+
+        if let Some(existing) = site.find_duplicate(storage.sites.values(), &self.profile) {
+            bail!("Web app is already installed in the target profile as {}", existing);
+        }
+
+        info!("Moving the web app");
+        storage
+            .profiles
+            .get_mut(&old_profile)
+            .context("Web app with invalid profile")?
+            .sites
+            .retain(|id| *id != self.id);
+        storage
+            .profiles
+            .get_mut(&self.profile)
+            .context("Profile does not exist")?
+            .sites
+            .push(self.id);
+
+        let site = storage.sites.get_mut(&self.id).context("Web app does not exist")?;
+        site.profile = self.profile;
+
+        // The web app keeps its ID, so only the existing entries need to be refreshed
+        if self.system_integration {
+            info!("Updating system integration");
+            integrations::install(&IntegrationInstallArgs {
+                site,
+                dirs: &dirs,
+                client: None,
+                update_manifest: false,
+                update_icons: false,
+                old_name: None,
+            })
+            .context("Failed to update system integration")?;
+        }
+
+        storage.write(&dirs)?;
+
+        info!("Web app moved!");
+        Ok(())
+    }
+}
+
+impl Run for SiteCloneCommand {
+    fn run(&self) -> Result<()> {
+        self._run()?;
+        Ok(())
+    }
+}
+
+impl SiteCloneCommand {
+    pub fn _run(&self) -> Result<Ulid> {
+        let dirs = ProjectDirs::new()?;
+
+        let storage = Storage::load(&dirs)?.unlock();
+
+        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+
+        if !storage.profiles.contains_key(&self.profile) {
+            bail!("Profile does not exist");
+        }
+
+        if let Some(existing) = site.find_duplicate(storage.sites.values(), &self.profile) {
+            bail!("Web app is already installed in the target profile as {}", existing);
+        }
+
+        // The clone starts with the currently approved manifest, without its history or pin
+        // It is not managed by a desired-state file, so it also does not get the same key
+        info!("Cloning the web app");
+        let ulid = Ulid::new();
+        let mut site = Site {
+            ulid,
+            profile: self.profile,
+            pending_manifest: None,
+            latest_cache: None,
+            history: vec![],
+            pinned: false,
+            failed_icons: false,
+            usage: SiteUsage::default(),
+            ..site.clone()
+        };
+        site.config.key = None;
+
+        if self.system_integration {
+            let client = construct_certificates_and_client(
+                &self.client.tls_root_certificates_der,
+                &self.client.tls_root_certificates_pem,
+                self.client.tls_danger_accept_invalid_certs,
+                self.client.tls_danger_accept_invalid_hostnames,
+            )?;
+
+            info!("Installing system integration");
+            integrations::install(&IntegrationInstallArgs {
+                site: &site,
+                dirs: &dirs,
+                client: Some(&client),
+                update_manifest: true,
+                update_icons: true,
+                old_name: None,
+            })
+            .context("Failed to install system integration")?;
+            site.failed_icons = integrations::take_icon_failures();
+        }
+
+        let mut storage = Storage::load(&dirs)?;
+        storage.insert_site(site)?;
+        storage.write(&dirs)?;
+
+        info!("Web app cloned: {}", ulid);
+        Ok(ulid)
+    }
+}
+
+impl Run for SiteManifestHistoryCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let storage = Storage::load_site(&dirs, &self.id)?.unlock();
+        let site = storage.sites.get(&self.id).context("Web app does not exist")?;
+
+        let status = if site.pinned { "current, pinned" } else { "current" };
+        println!("- {} ({})", manifest_name(&site.cache), status);
+
+        for revision in site.history.iter().rev() {
+            let replaced = OffsetDateTime::from(revision.id.datetime())
+                .format(&Rfc3339)
+                .context("Failed to format revision time")?;
+
+            println!(
+                "- {}: {} (replaced {})",
+                revision.id,
+                manifest_name(&revision.cache),
+                replaced
+            );
+        }
+
+        if site.history.is_empty() {
+            info!("No previous manifests available");
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the name from the cached raw manifest, so it can be shown without processing it.
+fn manifest_name(cache: &ManifestCache) -> String {
+    let manifest: Option<Value> = cache.body.as_deref().and_then(|body| from_str(body).ok());
+    let name = |field: &str| Some(manifest.as_ref()?.get(field)?.as_str()?.to_owned());
+    name("name").or_else(|| name("short_name")).unwrap_or_else(|| "(unnamed)".into())
+}
+
+impl Run for SiteManifestPinCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let mut storage = Storage::load(&dirs)?;
+
+        let site = storage.sites.get_mut(&self.id).context("Web app does not exist")?;
+        site.pinned = true;
+        storage.write(&dirs)?;
+
+        info!("Web app manifest pinned!");
+        Ok(())
+    }
+}
+
+impl Run for SiteManifestUnpinCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+        let mut storage = Storage::load(&dirs)?;
+
+        let site = storage.sites.get_mut(&self.id).context("Web app does not exist")?;
+        site.pinned = false;
+        storage.write(&dirs)?;
+
+        info!("Web app manifest unpinned!");
+        Ok(())
+    }
+}
+
+impl Run for SiteManifestRollbackCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+
+        let storage = Storage::load_site(&dirs, &self.id)?.unlock();
+
+        let mut site = storage.sites.get(&self.id).context("Web app does not exist")?.clone();
+        let old_name = site.name();
+
+        info!("Rolling back the web app manifest");
+        let changes = site.rollback_manifest(self.revision)?;
+        for change in changes {
+            info!("Manifest change: {}", change);
+        }
+
+        storage.policy.check_site(&site.config, &site.manifest)?;
+
+        if self.system_integration {
+            let client = construct_certificates_and_client(
+                &self.client.tls_root_certificates_der,
+                &self.client.tls_root_certificates_pem,
+                self.client.tls_danger_accept_invalid_certs,
+                self.client.tls_danger_accept_invalid_hostnames,
+            )?;
+
+            info!("Updating system integration");
+            integrations::install(&IntegrationInstallArgs {
+                site: &site,
+                dirs: &dirs,
+                client: Some(&client),
+                update_manifest: true,
+                update_icons: true,
+                old_name: Some(&old_name),
+            })
+            .context("Failed to update system integration")?;
+        }
+
+        let mut storage = Storage::load(&dirs)?;
+        storage.merge_site(site)?;
+        storage.write(&dirs)?;
+
+        warn!("Web app manifest is pinned, unpin it to allow manifest updates again");
+        info!("Web app manifest rolled back!");
+        Ok(())
+    }
+}
+
+impl Run for SiteCheckCommand {
+    fn run(&self) -> Result<()> {
+        let problems = self._run()?;
+
+        if problems.is_empty() {
+            info!("No problems found!");
+            return Ok(());
+        }
+
+        for problem in &problems {
+            println!("- {}", problem.description);
+        }
+
+        Ok(())
+    }
+}
+
+impl SiteCheckCommand {
+    pub fn _run(&self) -> Result<Vec<ManifestProblem>> {
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        if let Some(manifest_url) = &self.manifest_url {
+            let document_url = match &self.document_url {
+                Some(url) => url.clone(),
+                None => manifest_url.join(".")?,
+            };
+
+            info!("Checking the web app manifest");
+            return Ok(ManifestProblem::check_url(manifest_url, &document_url, &client));
+        }
+
+        let id = self.id.context("Web app ID or manifest URL is required")?;
+        let dirs = ProjectDirs::new()?;
+
+        let storage = Storage::load_site(&dirs, &id)?.unlock();
+        let site = storage.sites.get(&id).context("Web app does not exist")?;
+
+        info!("Checking the web app manifest");
+        Ok(ManifestProblem::check(&site.manifest, Some(&site.config), &client))
+    }
+}
+
+impl Run for SiteExportCommand {
+    fn run(&self) -> Result<()> {
+        let dirs = ProjectDirs::new()?;
+
+        let storage = Storage::load(&dirs)?.unlock();
+
+        let client = if self.icons {
+            Some(construct_certificates_and_client(
+                &self.client.tls_root_certificates_der,
+                &self.client.tls_root_certificates_pem,
+                self.client.tls_danger_accept_invalid_certs,
+                self.client.tls_danger_accept_invalid_hostnames,
+            )?)
+        } else {
+            None
+        };
+
+        info!("Exporting web apps");
+        let bundle = Bundle::new(&storage, &self.sites, client.as_ref())?;
+
+        let file = File::create(&self.output).context("Failed to create bundle file")?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &bundle)
+            .context("Failed to write bundle file")?;
+
+        info!("Web apps exported: {}", bundle.sites.len());
+        Ok(())
+    }
+}
+
+impl Run for SiteImportCommand {
+    fn run(&self) -> Result<()> {
+        let file = File::open(&self.input).context("Failed to open bundle file")?;
+        let bundle: Bundle =
+            serde_json::from_reader(BufReader::new(file)).context("Failed to parse bundle file")?;
+
+        let dirs = ProjectDirs::new()?;
+
+        let storage = Storage::load(&dirs)?.unlock();
+
+        let client = construct_certificates_and_client(
+            &self.client.tls_root_certificates_der,
+            &self.client.tls_root_certificates_pem,
+            self.client.tls_danger_accept_invalid_certs,
+            self.client.tls_danger_accept_invalid_hostnames,
+        )?;
+
+        info!("Importing web apps");
+        let imported = bundle.import(
+            &storage,
+            &dirs,
+            &client,
+            self.system_integration,
+            self.template.as_deref(),
+        )?;
+
+        let mut storage = Storage::load(&dirs)?;
+        let ulids = imported.insert_into(&mut storage)?;
+        storage.write(&dirs)?;
+
+        info!("Web apps imported: {}", ulids.len());
         Ok(())
     }
 }
@@ -13,10 +13,19 @@ use url::Url;
 use web_app_manifest::resources::IconResource;
 use web_app_manifest::types::{ImagePurpose, ImageSize};
 
-use crate::components::site::Site;
+use crate::components::site::{ShareData, Site};
 use crate::integrations::categories::XDG_CATEGORIES;
-use crate::integrations::utils::{download_icon, normalize_category_name, process_icons};
-use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::integrations::utils::{
+    download_icon,
+    normalize_category_name,
+    process_icons,
+    record_icon_failure,
+};
+use crate::integrations::{
+    IntegrationInstallArgs,
+    IntegrationUninstallArgs,
+    IntegrationVerifyArgs,
+};
 
 const BASE_DIRECTORIES_ERROR: &str = "Failed to determine base system directories";
 const CONVERT_ICON_URL_ERROR: &str = "Failed to convert icon URL";
@@ -45,6 +54,37 @@ fn update_application_cache(data: &Path) {
     let _ = Command::new("xdg-desktop-menu").arg("forceupdate").spawn();
 }
 
+/// Escape special characters for use in desktop entry values.
+///
+/// Translations come from the manifest, so they could otherwise
+/// inject additional keys into the desktop entry.
+fn escape_desktop(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r").replace('\t', "\\t")
+}
+
+/// Convert a language tag to a locale used by desktop entries.
+///
+/// Language tags like `pt-BR` are converted to `pt_BR`. Script and other
+/// subtags cannot be represented, so they are ignored.
+fn desktop_locale(tag: &str) -> Option<String> {
+    let mut subtags = tag.split(['-', '_']);
+
+    let language = subtags.next()?.to_lowercase();
+    if language.is_empty() || !language.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let country = subtags.find(|subtag| {
+        (subtag.len() == 2 && subtag.chars().all(|c| c.is_ascii_alphabetic()))
+            || (subtag.len() == 3 && subtag.chars().all(|c| c.is_ascii_digit()))
+    });
+
+    match country {
+        Some(country) => Some(format!("{}_{}", language, country.to_uppercase())),
+        None => Some(language),
+    }
+}
+
 //////////////////////////////
 // Implementation
 //////////////////////////////
@@ -163,6 +203,7 @@ fn store_icons(
         if let Err(error) = process().context(PROCESS_ICON_ERROR) {
             error!("{:?}", error);
             warn!("Falling back to the next available icon");
+            record_icon_failure();
         }
     }
 
@@ -218,15 +259,58 @@ fn create_desktop_entry(
     let directory = data.join("applications");
     let filename = directory.join(format!("{}.desktop", ids.classid));
 
+    // Shared URLs are passed to the URL parameter of the share target, or to the text parameter
+    // The `url` parameter is often omitted by share targets that expect links as text
+    let share = args.site.share_target().and_then(|target| match target.params {
+        ShareData { url: Some(_), .. } => Some("url"),
+        ShareData { text: Some(_), .. } => Some("text"),
+        _ => None,
+    });
+
+    // Store translations of the name, description and keywords that are not overwritten by the user
+    // Only the first translation is used if multiple language tags convert to the same locale
+    let (mut names, mut descriptions, mut keywords_list) =
+        (String::new(), String::new(), String::new());
+    let mut locales = vec![];
+    for (tag, translation) in args.site.translations() {
+        let locale = match desktop_locale(&tag) {
+            Some(locale) if !locales.contains(&locale) => locale,
+            _ => continue,
+        };
+
+        let config = &args.site.config;
+        if let Some(name) = translation.name.or(translation.short_name) {
+            if config.name.is_none() {
+                names += &format!("Name[{locale}]={}\n", escape_desktop(&name));
+            }
+        }
+        if let Some(description) = translation.description {
+            if config.description.is_none() {
+                descriptions += &format!("Comment[{locale}]={}\n", escape_desktop(&description));
+            }
+        }
+        if let Some(translated) = translation.keywords {
+            if config.keywords.is_none() {
+                let translated: Vec<_> = translated
+                    .iter()
+                    .map(|keyword| escape_desktop(keyword).replace(';', "\\;"))
+                    .collect();
+                keywords_list += &format!("Keywords[{locale}]={};\n", translated.join(";"));
+            }
+        }
+
+        locales.push(locale);
+    }
+
     // Store entry data
     let mut entry = format!(
         "[Desktop Entry]
 Type=Application
 Version=1.4
 Name={name}
-Comment={description}
-Keywords={keywords};
-Categories=GTK;WebApps;{categories};
+{names}Comment={description}
+{descriptions}Keywords={keywords};
+{translated_keywords}Categories=GTK;WebApps;{categories};
 Icon={icon}
 Exec={exe} site launch {id} --protocol %u
 Actions={actions}
@@ -239,9 +323,13 @@ StartupWMClass={wmclass}
         name = &ids.name,
         description = &ids.description,
         keywords = &args.site.keywords().join(";"),
+        names = &names,
+        descriptions = &descriptions,
+        translated_keywords = &keywords_list,
         categories = &categories.join(";"),
         actions = (0..args.site.manifest.shortcuts.len())
             .map(|i| i.to_string() + ";")
+            .chain(share.map(|_| "share;".into()))
             .collect::<String>(),
         protocols = args
             .site
@@ -283,6 +371,24 @@ Exec={exe} site launch {siteid} --url \"{url}\"
         entry += &action;
     }
 
+    // Store the share action
+    if let Some(field) = share {
+        let action = format!(
+            "
+[Desktop Action share]
+Name=Share
+Icon={icon}
+Exec={exe} site launch {siteid} --share {field}=%u
+",
+            siteid = &ids.ulid,
+            icon = &ids.classid,
+            field = field,
+            exe = &exe,
+        );
+
+        entry += &action;
+    }
+
     // Create the directory and write the file
     create_dir_all(directory).context(CREATE_APPLICATION_DIRECTORY_ERROR)?;
     write(filename, entry).context(WRITE_APPLICATION_FILE_ERROR)?;
@@ -318,6 +424,15 @@ fn remove_desktop_entry(classid: &str, data: &Path) -> Result<()> {
     Ok(())
 }
 
+fn remove_file_entry(classid: &str, data: &Path) -> Result<()> {
+    let package = data.join("mime/packages").join(format!("{classid}.xml"));
+    let entry = data.join("applications").join(format!("{classid}-files.desktop"));
+
+    let _ = remove_file(package);
+    let _ = remove_file(entry);
+    Ok(())
+}
+
 fn remove_startup_entry(classid: &str, config: &Path) -> Result<()> {
     let directory = config.join("autostart");
     let filename = directory.join(format!("{classid}.desktop"));
@@ -345,6 +460,9 @@ pub fn install(args: &IntegrationInstallArgs) -> Result<()> {
     }
 
     create_desktop_entry(args, &ids, &exe, &data).context("Failed to create application entry")?;
+    // File types are not registered until files can be passed to web apps
+    // Registrations from previous versions are removed, so files do not open without them
+    remove_file_entry(&ids.classid, &data).context("Failed to remove file handler entry")?;
     create_startup_entry(args, &ids, &data, &config).context("Failed to create startup entry")?;
     update_application_cache(&data);
 
@@ -361,8 +479,36 @@ pub fn uninstall(args: &IntegrationUninstallArgs) -> Result<()> {
 
     remove_icons(&ids.classid, data).context("Failed to remove web app icons")?;
     remove_desktop_entry(&ids.classid, data).context("Failed to remove application entry")?;
+    remove_file_entry(&ids.classid, data).context("Failed to remove file handler entry")?;
     remove_startup_entry(&ids.classid, config).context("Failed to remove startup entry")?;
     update_application_cache(data);
 
     Ok(())
 }
+
+#[inline]
+pub fn verify(args: &IntegrationVerifyArgs) -> Result<Vec<String>> {
+    let ids = SiteIds::create_for(args.site);
+
+    let base = directories::BaseDirs::new().context(BASE_DIRECTORIES_ERROR)?;
+    let data = base.data_dir();
+    let config = base.config_dir();
+
+    let mut missing = vec![];
+
+    if !data.join("applications").join(format!("{}.desktop", ids.classid)).exists() {
+        missing.push("application entry".into());
+    }
+
+    if !data.join("icons/hicolor/48x48/apps").join(format!("{}.png", ids.classid)).exists() {
+        missing.push("icon".into());
+    }
+
+    if args.site.config.launch_on_login
+        && !config.join("autostart").join(format!("{}.desktop", ids.classid)).exists()
+    {
+        missing.push("startup entry".into());
+    }
+
+    Ok(missing)
+}
@@ -24,9 +24,14 @@ use crate::integrations::utils::{
     download_icon,
     generate_icon,
     normalize_category_name,
+    record_icon_failure,
     sanitize_name,
 };
-use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::integrations::{
+    IntegrationInstallArgs,
+    IntegrationUninstallArgs,
+    IntegrationVerifyArgs,
+};
 
 const BASE_DIRECTORIES_ERROR: &str = "Failed to determine base system directories";
 const CONVERT_ICON_URL_ERROR: &str = "Failed to convert icon URL";
@@ -214,6 +219,7 @@ fn store_icons(target: &Path, name: &str, icons: &[IconResource], client: &Clien
                 Err(error) => {
                     error!("{:?}", error);
                     warn!("Falling back to the next available icon");
+                    record_icon_failure();
                 }
             }
         }
@@ -564,6 +570,27 @@ pub fn uninstall(args: &IntegrationUninstallArgs) -> Result<()> {
     Ok(())
 }
 
+#[inline]
+pub fn verify(args: &IntegrationVerifyArgs) -> Result<Vec<String>> {
+    let ulid = args.site.ulid.to_string();
+
+    let bundle = directories::BaseDirs::new()
+        .context(BASE_DIRECTORIES_ERROR)?
+        .home_dir()
+        .join("Applications")
+        .join(format!("{}.app", sanitize_name(&args.site.name(), &ulid)));
+
+    let mut missing = vec![];
+
+    if !bundle.join("Contents/Info.plist").exists() {
+        missing.push("application bundle".into());
+    } else if !bundle.join("Contents/Resources/app.icns").exists() {
+        missing.push("icon".into());
+    }
+
+    Ok(missing)
+}
+
 #[inline]
 pub fn launch(site: &Site, url: &Option<Url>, arguments: &[String]) -> Result<Child> {
     let name = site.name();
@@ -1,11 +1,18 @@
-use anyhow::Result;
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
 use cfg_if::cfg_if;
+use url::Url;
 
 #[rustfmt::skip]
 #[cfg(target_os = "macos")]
-use {crate::components::site::Site, std::process::Child, url::Url};
+use {crate::components::site::Site, std::process::Child};
 
-use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::integrations::{
+    IntegrationInstallArgs,
+    IntegrationUninstallArgs,
+    IntegrationVerifyArgs,
+};
 
 #[cfg(all(target_os = "windows", not(feature = "portable")))]
 mod windows;
@@ -53,8 +60,51 @@ pub fn uninstall(args: &IntegrationUninstallArgs) -> Result<()> {
     }
 }
 
+/// Returns descriptions of all system integration parts that are missing.
+#[inline]
+pub fn verify(args: &IntegrationVerifyArgs) -> Result<Vec<String>> {
+    cfg_if! {
+        if #[cfg(all(target_os = "windows", not(feature = "portable")))] {
+            windows::verify(args)
+        } else if #[cfg(all(target_os = "windows", feature = "portable"))] {
+            portableapps::verify(args)
+        } else if #[cfg(target_os = "linux")] {
+            linux::verify(args)
+        } else if #[cfg(target_os = "macos")] {
+            macos::verify(args)
+        } else {
+            compile_error!("Unknown operating system");
+        }
+    }
+}
+
 #[cfg(target_os = "macos")]
 #[inline]
 pub fn launch(site: &Site, url: &Option<Url>, arguments: &[String]) -> Result<Child> {
     macos::launch(site, url, arguments)
 }
+
+/// Opens the URL in the default system browser.
+///
+/// Only HTTP and HTTPS URLs are opened, as system handlers of other schemes
+/// could run programs or open local files.
+#[inline]
+pub fn open(url: &Url) -> Result<()> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        bail!("Only HTTP and HTTPS URLs can be opened in the default browser");
+    }
+
+    cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            let mut command = Command::new("rundll32");
+            command.arg("url.dll,FileProtocolHandler");
+        } else if #[cfg(target_os = "macos")] {
+            let mut command = Command::new("open");
+        } else {
+            let mut command = Command::new("xdg-open");
+        }
+    }
+
+    command.arg(url.as_str()).spawn().context("Failed to open URL in the default browser")?;
+    Ok(())
+}
@@ -10,7 +10,11 @@ use web_app_manifest::types::ImageSize;
 
 use crate::integrations::categories::PORTABLEAPPS_CATEGORIES;
 use crate::integrations::utils::{normalize_category_name, process_icons};
-use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::integrations::{
+    IntegrationInstallArgs,
+    IntegrationUninstallArgs,
+    IntegrationVerifyArgs,
+};
 
 #[derive(Debug, Clone, Copy)]
 struct PortableAppIcon {
@@ -131,8 +135,8 @@ fn create_appinfo(args: &IntegrationInstallArgs, appid: &str, appinfo: &Path) ->
     config.set("Details", "Name", Some(format!("{} Portable", args.site.name())));
     config.set("Details", "Description", args.site.description().into());
     config.set("Details", "Category", Some(category.into()));
-    config.set("Details", "Publisher", Some(args.site.domain()));
-    config.set("Details", "Homepage", Some(args.site.url()));
+    config.set("Details", "Publisher", Some(args.site.domain()?));
+    config.set("Details", "Homepage", Some(args.site.url()?));
     config.set("Details", "AppId", Some(appid.into()));
     config.set("Details", "Language", Some("Multilingual".into()));
     config.set("Version", "PackageVersion", Some("0.0.0.0".into()));
@@ -219,3 +223,26 @@ pub fn uninstall(args: &IntegrationUninstallArgs) -> Result<()> {
     let _ = remove_dir_all(package);
     Ok(())
 }
+
+#[inline]
+pub fn verify(args: &IntegrationVerifyArgs) -> Result<Vec<String>> {
+    let appid = format!("FFPWA-{}", args.site.ulid);
+
+    // Without the PortableApps.com Platform, the system integration is always skipped
+    let package = match get_portable_apps_directory(&args.dirs.executables) {
+        Some(package) => package.join(appid),
+        None => return Ok(vec![]),
+    };
+
+    let mut missing = vec![];
+
+    if !package.join("App").join("AppInfo").join("appinfo.ini").exists() {
+        missing.push("application info".into());
+    }
+
+    if !package.join("launch.vbs").exists() {
+        missing.push("launcher".into());
+    }
+
+    Ok(missing)
+}
@@ -35,7 +35,11 @@ use winreg::RegKey;
 
 use crate::components::site::Site;
 use crate::integrations::utils::{process_icons, sanitize_name};
-use crate::integrations::{IntegrationInstallArgs, IntegrationUninstallArgs};
+use crate::integrations::{
+    IntegrationInstallArgs,
+    IntegrationUninstallArgs,
+    IntegrationVerifyArgs,
+};
 
 const ADD_REMOVE_PROGRAMS_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Uninstall";
 const REGISTERED_APPLICATIONS_KEY: &str = r"Software\RegisteredApplications";
@@ -126,8 +130,8 @@ fn create_arp_entry(
     key.set_value("UninstallString", &format!("{} site uninstall --quiet {}", &exe, &ids.ulid))?;
     key.set_value("DisplayIcon", &icon)?;
     key.set_value("DisplayName", &ids.name)?;
-    key.set_value("Publisher", &args.site.domain())?;
-    key.set_value("URLInfoAbout", &args.site.url())?;
+    key.set_value("Publisher", &args.site.domain()?)?;
+    key.set_value("URLInfoAbout", &args.site.url()?)?;
     key.set_value("NoModify", &1u32)?;
     key.set_value("NoRepair", &1u32)?;
     key.set_value("Comments", &"Installed using PWAsForFirefox")?;
@@ -448,3 +452,32 @@ pub fn uninstall(args: &IntegrationUninstallArgs) -> Result<()> {
 
     Ok(())
 }
+
+#[inline]
+pub fn verify(args: &IntegrationVerifyArgs) -> Result<Vec<String>> {
+    let ids = SiteIds::create_for(args.site);
+    let name = sanitize_name(&ids.name, &ids.ulid);
+
+    let data = directories::BaseDirs::new()
+        .context("Failed to determine base system directories")?
+        .data_dir()
+        .to_owned();
+
+    let mut missing = vec![];
+
+    if !args.dirs.userdata.join("icons").join(&ids.ulid).join("site.ico").exists() {
+        missing.push("icon".into());
+    }
+
+    if !data.join(START_MENU_PROGRAMS_PATH).join(&name).with_extension("lnk").exists() {
+        missing.push("start menu shortcut".into());
+    }
+
+    if args.site.config.launch_on_login
+        && !data.join(STARTUP_PROGRAMS_PATH).join(&name).with_extension("lnk").exists()
+    {
+        missing.push("startup shortcut".into());
+    }
+
+    Ok(missing)
+}
@@ -5,11 +5,12 @@ use crate::directories::ProjectDirs;
 
 mod categories;
 mod implementation;
-mod utils;
+pub(crate) mod utils;
 
 #[cfg(target_os = "macos")]
 pub use implementation::launch;
-pub use implementation::{install, uninstall};
+pub use implementation::{install, open, uninstall, verify};
+pub use utils::take_icon_failures;
 
 #[derive(Debug, Clone)]
 pub struct IntegrationInstallArgs<'a> {
@@ -26,3 +27,9 @@ pub struct IntegrationUninstallArgs<'a> {
     pub site: &'a Site,
     pub dirs: &'a ProjectDirs,
 }
+
+#[derive(Debug, Clone)]
+pub struct IntegrationVerifyArgs<'a> {
+    pub site: &'a Site,
+    pub dirs: &'a ProjectDirs,
+}
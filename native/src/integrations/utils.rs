@@ -3,6 +3,7 @@
 use std::cmp::Ordering;
 use std::convert::TryInto;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 
 use ab_glyph::{Font, FontRef, PxScale};
 use anyhow::{bail, Context, Result};
@@ -20,6 +21,25 @@ use web_app_manifest::types::{ImagePurpose, ImageSize, Url as ManifestUrl};
 // Public
 //////////////////////////////
 
+/// Whether any icon has failed to process since the failures were last taken.
+///
+/// Failed icons are replaced with the next available or generated icon instead
+/// of failing the whole system integration, so they are only logged and recorded
+/// here. This way, updates can retry them even if the manifest has not changed.
+static ICON_FAILURES: AtomicBool = AtomicBool::new(false);
+
+/// Records that an icon has failed to process and was replaced with a fallback.
+#[inline]
+pub fn record_icon_failure() {
+    ICON_FAILURES.store(true, AtomicOrdering::Relaxed);
+}
+
+/// Returns whether any icon has failed to process since the last call.
+#[inline]
+pub fn take_icon_failures() -> bool {
+    ICON_FAILURES.swap(false, AtomicOrdering::Relaxed)
+}
+
 /// Remove all invalid filename characters and limit the length.
 ///
 /// Name is capped at 60 characters is sanitized using the [`sanitize_filename`]
@@ -78,6 +98,23 @@ pub fn download_icon(url: Url, client: &Client) -> Result<(Vec<u8>, String)> {
     }
 }
 
+/// Download and decode the icon to check whether it can be used.
+///
+/// Returns dimensions of raster icons, or `None` for SVG
+/// icons, which can be rendered at any size.
+pub fn inspect_icon(url: Url, client: &Client) -> Result<Option<(u32, u32)>> {
+    let (content, content_type) = download_icon(url, client).context("Failed to download icon")?;
+
+    if content_type == "image/svg+xml" {
+        let options = usvg::Options::default();
+        usvg::Tree::from_data(&content, &options.to_ref()).context("Failed to parse SVG icon")?;
+        return Ok(None);
+    }
+
+    let img = image::load_from_memory(&content).context("Failed to load icon")?;
+    Ok(Some((img.width(), img.height())))
+}
+
 /// Generate an icon from a letter.
 pub fn generate_icon(letter: char, size: &ImageSize) -> Result<RgbImage> {
     // Icon must have a fixed size
@@ -163,6 +200,7 @@ pub fn process_icons(
             Err(error) => {
                 error!("{:?}", error);
                 warn!("Falling back to the next available icon");
+                record_icon_failure();
             }
         }
     }
@@ -0,0 +1,119 @@
+use std::cmp::Reverse;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+use serde::Serialize;
+use ulid::Ulid;
+
+use crate::directories::ProjectDirs;
+use crate::storage::layout;
+
+/// How many storage backups are kept before the oldest ones are removed.
+const MAX_BACKUPS: usize = 10;
+
+/// Whether the storage has already been backed up by this process.
+static CREATED: AtomicBool = AtomicBool::new(false);
+
+/// Information about a storage backup.
+#[derive(Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct Backup {
+    /// A backup ID.
+    ///
+    /// Stored as the ULID format, so backups sort by their creation time.
+    pub id: Ulid,
+
+    /// Time when the backup was created, in milliseconds since the Unix epoch.
+    pub created: u64,
+
+    /// Size of the backup in bytes.
+    pub size: u64,
+}
+
+#[inline]
+fn directory(dirs: &ProjectDirs) -> PathBuf {
+    dirs.userdata.join("backups")
+}
+
+#[inline]
+fn filename(dirs: &ProjectDirs, id: &Ulid) -> PathBuf {
+    directory(dirs).join(format!("config-{id}.json"))
+}
+
+/// Backs up the current storage and removes the oldest backups.
+///
+/// Backups always contain the whole storage in a single file, so they are
+/// independent of the storage layout. Does nothing if the storage has not
+/// been written yet.
+///
+/// Only the first backup in a process is created. Commands and connector
+/// requests that write the storage multiple times therefore keep a single
+/// backup of the storage from before they started, instead of replacing
+/// older backups with intermediate states.
+pub fn create(dirs: &ProjectDirs) -> Result<()> {
+    if CREATED.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    let document = match layout::read_document(dirs, None)? {
+        Some((document, _)) => document,
+        None => return Ok(()),
+    };
+
+    let data = serde_json::to_vec(&document).context("Failed to serialize storage")?;
+    create_dir_all(directory(dirs)).context("Failed to create backup directory")?;
+    write(filename(dirs, &Ulid::new()), data).context("Failed to write storage to backup")?;
+
+    for backup in list(dirs)?.iter().skip(MAX_BACKUPS) {
+        if let Err(error) = remove_file(filename(dirs, &backup.id)) {
+            warn!("Failed to remove old backup {}: {}", backup.id, error);
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists all available backups, from the newest to the oldest.
+pub fn list(dirs: &ProjectDirs) -> Result<Vec<Backup>> {
+    const LIST_ERROR: &str = "Failed to list backups";
+
+    let directory = directory(dirs);
+    if !directory.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut backups = vec![];
+
+    for entry in read_dir(directory).context(LIST_ERROR)? {
+        let entry = entry.context(LIST_ERROR)?;
+        let name = entry.file_name();
+
+        // Ignore all files that do not look like backups
+        let id = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("config-"))
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|id| Ulid::from_string(id).ok());
+
+        if let Some(id) = id {
+            let size = entry.metadata().context(LIST_ERROR)?.len();
+            backups.push(Backup { id, created: id.timestamp_ms(), size });
+        }
+    }
+
+    backups.sort_unstable_by_key(|backup| Reverse(backup.id));
+    Ok(backups)
+}
+
+/// Reads the content of a backup.
+pub fn read(dirs: &ProjectDirs, id: &Ulid) -> Result<String> {
+    let filename = filename(dirs, id);
+
+    if !filename.exists() {
+        bail!("Backup does not exist");
+    }
+
+    read_to_string(filename).context("Failed to read backup")
+}
@@ -0,0 +1,406 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read, read_dir, remove_file, rename, File};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde_json::{Map, Value};
+use tempfile::NamedTempFile;
+use ulid::Ulid;
+
+use crate::directories::ProjectDirs;
+
+const STORAGE_READ_ERROR: &str = "Failed to read storage file";
+const STORAGE_WRITE_ERROR: &str = "Failed to write storage file";
+
+/// Hashes of the index, profile and web app files as they were loaded.
+///
+/// Used to skip writing files whose content has not changed. Also contains IDs
+/// of profiles and web apps whose files were missing, so they are kept in the index.
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+pub struct Hashes {
+    pub index: Option<u64>,
+    pub profiles: BTreeMap<Ulid, u64>,
+    pub sites: BTreeMap<Ulid, u64>,
+    pub missing_profiles: Vec<Ulid>,
+    pub missing_sites: Vec<Ulid>,
+}
+
+#[inline]
+fn directory(dirs: &ProjectDirs) -> PathBuf {
+    dirs.userdata.join("storage")
+}
+
+#[inline]
+fn hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn serialize(value: &Value) -> Result<Vec<u8>> {
+    if cfg!(debug_assertions) {
+        Ok(serde_json::to_vec_pretty(value)?)
+    } else {
+        Ok(serde_json::to_vec(value)?)
+    }
+}
+
+/// Reads and parses a JSON file, and returns it with the hash of its content.
+fn read_file(path: &Path) -> Result<(Value, u64)> {
+    let data = read(path).context(STORAGE_READ_ERROR)?;
+    let value = serde_json::from_slice(&data).context(STORAGE_READ_ERROR)?;
+    Ok((value, hash(&data)))
+}
+
+/// Writes a file atomically.
+///
+/// The data is first written to a temporary file in the same directory,
+/// which is synced to the disk and then renamed over the target file. This
+/// way, the file is never left truncated when the program crashes.
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    let parent = path.parent().context(STORAGE_WRITE_ERROR)?;
+    create_dir_all(parent).context(STORAGE_WRITE_ERROR)?;
+
+    let mut file = NamedTempFile::new_in(parent).context(STORAGE_WRITE_ERROR)?;
+    file.write_all(data).context(STORAGE_WRITE_ERROR)?;
+    file.as_file().sync_all().context(STORAGE_WRITE_ERROR)?;
+    file.persist(path).context(STORAGE_WRITE_ERROR)?;
+
+    Ok(())
+}
+
+/// Syncs the directory, so renames inside it survive a crash.
+///
+/// This is not possible on Windows, where renames are already durable.
+#[inline]
+fn sync_directory(_path: &Path) {
+    #[cfg(unix)]
+    if let Ok(directory) = File::open(_path) {
+        let _ = directory.sync_all();
+    }
+}
+
+/// Takes a list of IDs from the index.
+fn take_ids(index: &mut Map<String, Value>, key: &str) -> Result<Vec<Ulid>> {
+    match index.remove(key) {
+        Some(ids) => serde_json::from_value(ids).context("Storage index contains invalid IDs"),
+        None => Ok(vec![]),
+    }
+}
+
+/// Reads a profile or web app file, or returns `None` if it is missing.
+fn read_entity(directory: &Path, kind: &str, id: &Ulid) -> Result<Option<(Value, u64)>> {
+    let filename = directory.join(kind).join(format!("{id}.json"));
+
+    // Missing files should not make the whole storage unusable
+    // They are still kept in the index, so they can be restored later
+    if !filename.exists() {
+        warn!("Storage file for {} {} is missing, skipping it", kind, id);
+        return Ok(None);
+    }
+
+    Ok(Some(read_file(&filename)?))
+}
+
+/// Serialized profile or web app files and their IDs.
+type Serialized = Vec<(Ulid, Vec<u8>)>;
+
+/// Serializes profiles or web apps, and returns IDs of all of them and those that have changed.
+///
+/// IDs of profiles or web apps whose files were missing when they were read
+/// are also returned, so they are not removed from the index.
+fn serialize_entities(
+    entities: Map<String, Value>,
+    hashes: &BTreeMap<Ulid, u64>,
+    missing: &[Ulid],
+) -> Result<(Vec<Ulid>, Serialized)> {
+    let mut ids = vec![];
+    let mut changed = vec![];
+
+    for (id, entity) in entities {
+        let id = Ulid::from_string(&id).context("Storage contains invalid ID")?;
+        let data = serialize(&entity).context(STORAGE_WRITE_ERROR)?;
+
+        if hashes.get(&id) != Some(&hash(&data)) {
+            changed.push((id, data));
+        }
+
+        ids.push(id);
+    }
+
+    // Keep the same order as when the index is written normally, so its hash does not change
+    let missing: Vec<_> = missing.iter().filter(|id| !ids.contains(id)).collect();
+    ids.extend(missing);
+    ids.sort();
+
+    Ok((ids, changed))
+}
+
+/// Writes serialized profile or web app files.
+fn write_entities(directory: &Path, entities: Serialized) -> Result<()> {
+    for (id, data) in entities {
+        write_file(&directory.join(format!("{id}.json")), &data)?;
+    }
+
+    sync_directory(directory);
+    Ok(())
+}
+
+/// Removes profile or web app files that are not in the storage anymore.
+fn remove_entities(directory: &Path, ids: &[Ulid]) -> Result<()> {
+    if !directory.exists() {
+        return Ok(());
+    }
+
+    for entry in read_dir(directory).context("Failed to read storage directory")? {
+        let path = entry.context("Failed to read storage directory")?.path();
+        let id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".json"))
+            .and_then(|id| Ulid::from_string(id).ok());
+
+        if let Some(id) = id {
+            if !ids.contains(&id) {
+                if let Err(error) = remove_file(&path) {
+                    warn!("Failed to remove storage file {}: {}", path.display(), error);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the monolithic `config.json` into separate files.
+///
+/// The original file is kept as `config.json.migrated`.
+fn split_monolithic(dirs: &ProjectDirs) -> Result<()> {
+    info!("Splitting storage into separate files");
+
+    let source = dirs.userdata.join("config.json");
+    let (document, _) = read_file(&source)?;
+
+    write(dirs, document, &Hashes::default(), || Ok(()))?;
+    rename(&source, dirs.userdata.join("config.json.migrated"))
+        .context("Failed to rename original storage file")?;
+
+    Ok(())
+}
+
+/// Reads the storage document from separate files.
+///
+/// The storage is stored in the `storage` directory of the user data, with
+/// an `index.json` file that contains the config and IDs of all profiles and
+/// web apps, and separate files for each profile and web app in `profiles`
+/// and `sites` directories. The storage document is assembled into the same
+/// structure as the serialized storage, so it can be migrated and parsed.
+///
+/// If the web app ID is provided, only that web app and its profile are read.
+/// Otherwise, all profiles and web apps are read.
+///
+/// The storage in the old `config.json` file is split into separate files
+/// first. Returns `None` if the storage has not been written yet.
+pub fn read_document(dirs: &ProjectDirs, site: Option<&Ulid>) -> Result<Option<(Value, Hashes)>> {
+    let directory = directory(dirs);
+    let filename = directory.join("index.json");
+
+    if !filename.exists() {
+        if !dirs.userdata.join("config.json").exists() {
+            return Ok(None);
+        }
+
+        split_monolithic(dirs).context("Failed to migrate storage into separate files")?;
+    }
+
+    let (index, index_hash) = read_file(&filename)?;
+    let mut index = match index {
+        Value::Object(index) => index,
+        _ => bail!("Storage index is not a JSON object"),
+    };
+
+    let profile_ids = take_ids(&mut index, "profiles")?;
+    let site_ids = take_ids(&mut index, "sites")?;
+
+    let mut hashes = Hashes { index: Some(index_hash), ..Hashes::default() };
+    let mut profiles = Map::new();
+    let mut sites = Map::new();
+
+    for id in site_ids.iter().filter(|id| site.is_none() || site == Some(*id)) {
+        match read_entity(&directory, "sites", id)? {
+            Some((entity, hash)) => {
+                sites.insert(id.to_string(), entity);
+                hashes.sites.insert(*id, hash);
+            }
+            None => hashes.missing_sites.push(*id),
+        }
+    }
+
+    // When reading a single web app, only its profile is needed
+    let needed = |id: &Ulid| {
+        let id = id.to_string();
+        site.is_none() || sites.values().any(|site| site["profile"].as_str() == Some(&id))
+    };
+
+    for id in profile_ids.iter().filter(|id| needed(id)) {
+        match read_entity(&directory, "profiles", id)? {
+            Some((entity, hash)) => {
+                profiles.insert(id.to_string(), entity);
+                hashes.profiles.insert(*id, hash);
+            }
+            None => hashes.missing_profiles.push(*id),
+        }
+    }
+
+    index.insert("profiles".into(), Value::Object(profiles));
+    index.insert("sites".into(), Value::Object(sites));
+    Ok(Some((Value::Object(index), hashes)))
+}
+
+/// Updates a single web app file in place.
+///
+/// The update receives the raw web app object and can change any of its members.
+/// Other files and the index are not read or written. Does nothing if the web
+/// app file does not exist.
+pub fn update_site<F>(dirs: &ProjectDirs, id: &Ulid, update: F) -> Result<()>
+where
+    F: FnOnce(&mut Map<String, Value>) -> Result<()>,
+{
+    let directory = directory(dirs);
+    let mut site = match read_entity(&directory, "sites", id)? {
+        Some((Value::Object(site), _)) => site,
+        Some(_) => bail!("Storage file for site {} is not a JSON object", id),
+        None => return Ok(()),
+    };
+
+    update(&mut site)?;
+
+    let data = serialize(&Value::Object(site)).context(STORAGE_WRITE_ERROR)?;
+    write_file(&directory.join("sites").join(format!("{id}.json")), &data)?;
+    sync_directory(&directory.join("sites"));
+
+    Ok(())
+}
+
+/// Writes the storage document into separate files.
+///
+/// Only files that have changed since they were read are written. If any
+/// file has changed, the `before` function is called first, so the previous
+/// storage can be backed up. The index is written last, so the storage stays
+/// consistent if the program crashes in the middle of writing. Files of removed
+/// profiles and web apps are removed afterwards. Profiles and web apps whose
+/// files were missing when the storage was read stay in the index.
+pub fn write<F>(dirs: &ProjectDirs, document: Value, hashes: &Hashes, before: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()>,
+{
+    let mut index = match document {
+        Value::Object(index) => index,
+        _ => bail!("Storage is not a JSON object"),
+    };
+
+    let mut take_entities = |key: &str| match index.remove(key) {
+        Some(Value::Object(entities)) => entities,
+        _ => Map::new(),
+    };
+
+    let profiles = take_entities("profiles");
+    let sites = take_entities("sites");
+
+    let (profile_ids, changed_profiles) =
+        serialize_entities(profiles, &hashes.profiles, &hashes.missing_profiles)?;
+    let (site_ids, changed_sites) =
+        serialize_entities(sites, &hashes.sites, &hashes.missing_sites)?;
+
+    index.insert("profiles".into(), serde_json::to_value(&profile_ids)?);
+    index.insert("sites".into(), serde_json::to_value(&site_ids)?);
+    let data = serialize(&Value::Object(index)).context(STORAGE_WRITE_ERROR)?;
+
+    // The index contains IDs of all profiles and web apps, so it also changes when any is removed
+    if hashes.index == Some(hash(&data)) && changed_profiles.is_empty() && changed_sites.is_empty()
+    {
+        return Ok(());
+    }
+
+    before()?;
+
+    let directory = directory(dirs);
+    write_entities(&directory.join("profiles"), changed_profiles)?;
+    write_entities(&directory.join("sites"), changed_sites)?;
+
+    write_file(&directory.join("index.json"), &data)?;
+    sync_directory(&directory);
+
+    remove_entities(&directory.join("profiles"), &profile_ids)?;
+    remove_entities(&directory.join("sites"), &site_ids)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, write};
+    use std::path::Path;
+
+    use serde_json::Value;
+    use tempfile::tempdir;
+
+    use crate::directories::ProjectDirs;
+    use crate::storage::Storage;
+
+    /// Storage written by the native program before it was split into separate files.
+    const V0: &str = include_str!("fixtures/v0.json");
+
+    const SITE: &str = "01GQ6PY3N6C0K1XN5HTJ3Q9PWB";
+
+    fn project_dirs(userdata: &Path) -> ProjectDirs {
+        ProjectDirs {
+            executables: userdata.join("executables"),
+            sysdata: userdata.join("sysdata"),
+            userdata: userdata.into(),
+        }
+    }
+
+    /// Reads the web app the same way as the runtime when launching it (see `boot.jsm`).
+    fn read_runtime_site(dirs: &ProjectDirs, id: &str) -> Value {
+        let filename = dirs.userdata.join("storage").join("sites").join(format!("{id}.json"));
+        serde_json::from_str(&read_to_string(filename).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn runtime_reads_sites_after_migration() {
+        let directory = tempdir().unwrap();
+        let dirs = project_dirs(directory.path());
+        write(dirs.userdata.join("config.json"), V0).unwrap();
+
+        let storage = Storage::load(&dirs).unwrap();
+        storage.write(&dirs).unwrap();
+
+        let original: Value = serde_json::from_str(V0).unwrap();
+        let site = read_runtime_site(&dirs, SITE);
+
+        assert_eq!(site["config"]["name"], original["sites"][SITE]["config"]["name"]);
+        assert_eq!(site["config"]["start_url"], Value::Null);
+        assert_eq!(site["manifest"]["name"], original["sites"][SITE]["manifest"]["name"]);
+        assert!(!dirs.userdata.join("config.json").exists());
+        assert!(dirs.userdata.join("config.json.migrated").exists());
+    }
+
+    #[test]
+    fn runtime_reads_sites_after_write() {
+        let directory = tempdir().unwrap();
+        let dirs = project_dirs(directory.path());
+        write(dirs.userdata.join("config.json"), V0).unwrap();
+
+        let mut storage = Storage::load(&dirs).unwrap();
+        storage.sites.values_mut().for_each(|site| site.config.name = Some("Changed".into()));
+        storage.write(&dirs).unwrap();
+
+        let site = read_runtime_site(&dirs, SITE);
+        assert_eq!(site["config"]["name"], "Changed");
+    }
+}
@@ -0,0 +1,142 @@
+use std::convert::TryFrom;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde_json::{Map, Value};
+
+/// The current version of the storage schema.
+///
+/// Needs to be incremented every time a field is renamed, removed or restructured
+/// in a way that cannot be handled by `#[serde(default)]`. A migration that upgrades
+/// the previous version must then also be added to [`MIGRATIONS`].
+pub const STORAGE_VERSION: u32 = 1;
+
+/// A function that upgrades the storage document by one version.
+type Migration = fn(&mut Map<String, Value>) -> Result<()>;
+
+/// All storage migrations, in order.
+///
+/// Migration at index `n` upgrades the document from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; STORAGE_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Storage created before schema versioning was introduced.
+///
+/// Its structure is the same as in the first versioned schema,
+/// so the only thing that needs to be done is setting the version.
+fn migrate_v0_to_v1(_document: &mut Map<String, Value>) -> Result<()> {
+    Ok(())
+}
+
+/// Upgrades the storage document to the current schema version.
+///
+/// Documents without a version are treated as version 0. Migrations are applied
+/// step by step until the document reaches the current version. Documents with
+/// a newer version than supported are refused, so they are not overwritten with
+/// data that would be missing all unknown fields.
+pub fn migrate(document: &mut Value) -> Result<()> {
+    let document = document.as_object_mut().context("Storage is not a JSON object")?;
+
+    let version = match document.get("version") {
+        Some(version) => {
+            let version = version.as_u64().context("Storage version is not a number")?;
+            u32::try_from(version).context("Storage version is out of range")?
+        }
+        None => 0,
+    };
+
+    if version > STORAGE_VERSION {
+        bail!(
+            "Storage version {} is newer than supported version {}, please update the native program",
+            version,
+            STORAGE_VERSION
+        );
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!("Migrating storage from version {} to version {}", from, from + 1);
+        migration(document).context(format!("Failed to migrate storage from version {from}"))?;
+        document.insert("version".into(), Value::from(from + 1));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use ulid::Ulid;
+
+    use super::{migrate, STORAGE_VERSION};
+    use crate::storage::Storage;
+
+    /// Storage written by the native program before schema versioning was introduced.
+    const V0: &str = include_str!("fixtures/v0.json");
+
+    fn fixture(data: &str) -> Value {
+        serde_json::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn migrates_v0_to_current() {
+        let original = fixture(V0);
+        let mut document = original.clone();
+        migrate(&mut document).unwrap();
+
+        assert_eq!(document["version"], json!(STORAGE_VERSION));
+        for key in ["profiles", "sites", "arguments", "variables", "config"] {
+            assert_eq!(document[key], original[key], "{} changed during migration", key);
+        }
+    }
+
+    #[test]
+    fn parses_migrated_v0() {
+        let mut document = fixture(V0);
+        migrate(&mut document).unwrap();
+        let storage: Storage = serde_json::from_value(document).unwrap();
+
+        let profile = Ulid::from_string("01GQ6PXZFD8TRJ7WW0F9J1ZV6E").unwrap();
+        let site = Ulid::from_string("01GQ6PY3N6C0K1XN5HTJ3Q9PWB").unwrap();
+
+        assert_eq!(storage.version, STORAGE_VERSION);
+        assert_eq!(storage.profiles.len(), 2);
+        assert_eq!(storage.profiles[&profile].sites, vec![site]);
+        assert_eq!(storage.sites[&site].profile, profile);
+        assert_eq!(storage.sites[&site].config.name.as_deref(), Some("Example"));
+        assert!(storage.sites[&site].config.launch_on_browser);
+        assert_eq!(storage.arguments, vec!["--safe-mode"]);
+        assert!(storage.config.always_patch);
+    }
+
+    #[test]
+    fn keeps_current_version() {
+        let original = json!({ "version": STORAGE_VERSION, "arguments": ["--safe-mode"] });
+        let mut document = original.clone();
+        migrate(&mut document).unwrap();
+
+        assert_eq!(document, original);
+    }
+
+    #[test]
+    fn refuses_newer_version() {
+        let original = json!({ "version": STORAGE_VERSION + 1, "arguments": ["--safe-mode"] });
+        let mut document = original.clone();
+        let error = migrate(&mut document).unwrap_err();
+
+        assert!(error.to_string().contains("newer than supported"), "{}", error);
+        assert_eq!(document, original);
+    }
+
+    #[test]
+    fn refuses_invalid_version() {
+        for version in [json!(u64::from(u32::MAX) + 1), json!(-1), json!("1"), json!(1.5)] {
+            let mut document = json!({ "version": version });
+            assert!(migrate(&mut document).is_err(), "{} was accepted", version);
+        }
+    }
+
+    #[test]
+    fn refuses_non_object() {
+        let mut document = json!([]);
+        assert!(migrate(&mut document).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use smart_default::SmartDefault;
+use ulid::Ulid;
+use url::Url;
+
+use crate::components::profile::Profile;
+use crate::components::site::{Site, SiteUsage};
+use crate::directories::ProjectDirs;
+use crate::storage::layout::Hashes;
+use crate::storage::migrations::{migrate, STORAGE_VERSION};
+use crate::storage::policy::Policy;
+
+pub mod backups;
+mod layout;
+mod migrations;
+pub mod policy;
+
+const STORAGE_LOAD_ERROR: &str = "Failed to load storage";
+const STORAGE_SAVE_ERROR: &str = "Failed to save storage";
+const STORAGE_LOCK_ERROR: &str = "Failed to lock storage";
+
+/// How long to wait for another process to release the storage lock.
+const STORAGE_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Advisory lock that prevents concurrent modifications of the storage.
+///
+/// The lock is acquired on a separate `config.lock` file, because storage
+/// files themselves are replaced on every write. It is released when the last reference
+/// to it is dropped.
+#[derive(Debug)]
+struct StorageLock(File);
+
+impl StorageLock {
+    fn acquire(dirs: &ProjectDirs) -> Result<Self> {
+        let filename = dirs.userdata.join("config.lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(filename)
+            .context(STORAGE_LOCK_ERROR)?;
+
+        // Wait for other processes to finish their operations, but do not block forever
+        // Otherwise, a stuck process (or an unanswered prompt) would block all other clients
+        let start = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(_) => return Ok(Self(file)),
+                Err(error) if error.kind() == fs2::lock_contended_error().kind() => {}
+                Err(error) => return Err(error).context(STORAGE_LOCK_ERROR),
+            }
+
+            if start.elapsed() > STORAGE_LOCK_TIMEOUT {
+                bail!("Storage is locked by another process");
+            }
+
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Drop for StorageLock {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = self.0.unlock();
+    }
+}
+
+impl PartialEq for StorageLock {
+    /// Locks do not contain any data, so they never affect storage equality.
+    #[inline]
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, SmartDefault)]
+#[serde(default)]
+pub struct Config {
+    /// Whether the runtime and profile should always be patched when launching a web app.
+    ///
+    /// Does not have any effect on macOS, where web apps are
+    /// always patched to correctly display their names.
+    pub always_patch: bool,
+
+    /// Whether URLs outside the web app scope should be opened in the default browser.
+    ///
+    /// URLs passed when launching a web app, including resolved protocol
+    /// handler URLs, need to be within the web app scope or one of its
+    /// enabled URL handlers. Other URLs are rejected unless this is enabled.
+    pub open_out_of_scope_urls: bool,
+
+    /// Whether the runtime should use Wayland Display Server.
+    ///
+    /// This sets `MOZ_ENABLE_WAYLAND` environment variable.
+    ///
+    /// Only affects Linux, on supported desktop environments.
+    /// May be overwritten with a system environment variable.
+    pub runtime_enable_wayland: bool,
+
+    /// Whether the runtime should use X Input Extension 2.
+    ///
+    /// This sets `MOZ_USE_XINPUT2` environment variable.
+    ///
+    /// Only affects Linux, on supported desktop environments.
+    /// May be overwritten with a system environment variable.
+    pub runtime_use_xinput2: bool,
+
+    /// Whether the runtime should use XDG Desktop Portals.
+    ///
+    /// This sets `GTK_USE_PORTAL` environment variable.
+    ///
+    /// Only affects Linux, on supported desktop environments.
+    /// May be overwritten with a system environment variable.
+    pub runtime_use_portals: bool,
+}
+
+#[non_exhaustive]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, SmartDefault)]
+#[serde(default)]
+pub struct Storage {
+    /// A version of the storage schema.
+    ///
+    /// Used to upgrade storage created by older versions using migrations.
+    /// Storage with a newer version than supported cannot be loaded.
+    #[default(STORAGE_VERSION)]
+    pub version: u32,
+
+    /// A map of profiles and their IDs.
+    #[default([(Ulid::nil(), Profile::default())].iter().cloned().collect())]
+    pub profiles: BTreeMap<Ulid, Profile>,
+
+    /// A map of web apps and their IDs.
+    pub sites: BTreeMap<Ulid, Site>,
+
+    /// Arguments to be passed to the Firefox runtime.
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime.
+    pub variables: BTreeMap<String, String>,
+
+    /// Config of the native program.
+    pub config: Config,
+
+    /// Manifest URLs of web apps from the policy that have already been preinstalled.
+    ///
+    /// Web apps are only preinstalled once, so they stay
+    /// uninstalled if the user decides to uninstall them.
+    pub preinstalled: Vec<Url>,
+
+    /// Policy set by the administrator.
+    ///
+    /// Loaded separately from the system-wide policy file.
+    #[serde(skip)]
+    pub policy: Policy,
+
+    /// Hashes of profile and web app files as they were loaded.
+    #[serde(skip)]
+    hashes: Hashes,
+
+    /// Whether only some profiles and web apps have been loaded.
+    #[serde(skip)]
+    partial: bool,
+
+    /// Lock that is held from loading the storage until it is dropped.
+    #[serde(skip)]
+    lock: Option<Arc<StorageLock>>,
+}
+
+impl Storage {
+    /// Loads the storage and locks it until it is dropped.
+    ///
+    /// Holding the lock across the whole load-modify-write cycle prevents
+    /// concurrent processes from overwriting each other's changes. Another
+    /// process trying to load the storage will wait for the lock to be released
+    /// and fail if this takes too long.
+    #[inline]
+    pub fn load(dirs: &ProjectDirs) -> Result<Self> {
+        Self::load_selected(dirs, None)
+    }
+
+    /// Loads only the web app and its profile, and locks the storage until it is dropped.
+    ///
+    /// Should be used for operations that only need a single web app, as it
+    /// does not need to read all other profiles and web apps. The partially
+    /// loaded storage cannot be written.
+    #[inline]
+    pub fn load_site(dirs: &ProjectDirs, id: &Ulid) -> Result<Self> {
+        Self::load_selected(dirs, Some(id))
+    }
+
+    fn load_selected(dirs: &ProjectDirs, site: Option<&Ulid>) -> Result<Self> {
+        let lock = Some(Arc::new(StorageLock::acquire(dirs)?));
+        let policy = Policy::load(dirs)?;
+
+        // Policy defaults also need to be applied to the new storage
+        let (document, hashes) =
+            match layout::read_document(dirs, site).context(STORAGE_LOAD_ERROR)? {
+                Some((document, hashes)) => (document, hashes),
+                None => (Value::Object(Map::new()), Hashes::default()),
+            };
+
+        let storage = Self::parse(document, &policy).context(STORAGE_LOAD_ERROR)?;
+        Ok(Self { lock, policy, hashes, partial: site.is_some(), ..storage })
+    }
+
+    /// Migrates the storage document to the current version, applies the policy and parses it.
+    fn parse(mut document: Value, policy: &Policy) -> Result<Self> {
+        migrate(&mut document)?;
+        policy.apply(&mut document);
+        Ok(serde_json::from_value(document)?)
+    }
+
+    /// Replaces the storage content with the content of a backup.
+    ///
+    /// The restored storage is not written automatically. Once it is
+    /// written, the replaced content is backed up as a new backup,
+    /// so restoring can also be undone.
+    pub fn restore(&mut self, dirs: &ProjectDirs, id: &Ulid) -> Result<()> {
+        let data = backups::read(dirs, id)?;
+        let document = serde_json::from_str(&data).context("Failed to load backup")?;
+        let storage = Self::parse(document, &self.policy).context("Failed to load backup")?;
+
+        *self = Self {
+            lock: self.lock.take(),
+            policy: self.policy.clone(),
+            hashes: self.hashes.clone(),
+            partial: self.partial,
+            ..storage
+        };
+        Ok(())
+    }
+
+    /// Releases the storage lock, so other processes can access the storage.
+    ///
+    /// Should be used before long-running operations, such as downloads or prompts.
+    /// Writing the storage after it has been unlocked is still possible, but may
+    /// overwrite changes made by other processes in the meantime. Operations that
+    /// modify the storage should load it again and merge their changes instead.
+    #[inline]
+    pub fn unlock(self) -> Self {
+        Self { lock: None, ..self }
+    }
+
+    /// Merges the manifest of the web app that was updated while the storage was unlocked.
+    ///
+    /// Operations that download data work on an unlocked copy of the storage, so
+    /// they do not block other processes. Once they finish, the storage is loaded
+    /// again and the downloaded data is merged into the current web app. Only the
+    /// manifest, its caches, pending changes, history, pin and icon state are taken
+    /// from the updated copy. The config, profile and usage statistics are kept, as
+    /// they may have been changed in the meantime, so operations that change the
+    /// config need to apply their changes to the returned web app again.
+    pub fn merge_site(&mut self, site: Site) -> Result<&mut Site> {
+        let existing = self.sites.get_mut(&site.ulid).context("Web app does not exist anymore")?;
+        existing.manifest = site.manifest;
+        existing.cache = site.cache;
+        existing.pending_manifest = site.pending_manifest;
+        existing.latest_cache = site.latest_cache;
+        existing.history = site.history;
+        existing.pinned = site.pinned;
+        existing.failed_icons = site.failed_icons;
+        Ok(existing)
+    }
+
+    /// Adds the web app that was created while the storage was unlocked to its profile.
+    ///
+    /// See [`Storage::merge_site`] for why web apps are created on an unlocked copy.
+    pub fn insert_site(&mut self, site: Site) -> Result<()> {
+        let profile = self.profiles.get_mut(&site.profile).context("Profile does not exist")?;
+        profile.sites.push(site.ulid);
+        self.sites.insert(site.ulid, site);
+        Ok(())
+    }
+
+    /// Removes the web app whose file is missing from the storage index.
+    ///
+    /// Web apps whose files are missing are kept in the index, so their files
+    /// can still be restored. Once they are removed from their profile, they
+    /// should also be removed from the index.
+    #[inline]
+    pub fn forget_missing_site(&mut self, id: &Ulid) {
+        self.hashes.missing_sites.retain(|missing| missing != id);
+    }
+
+    /// Updates the usage statistics of the web app.
+    ///
+    /// Usage statistics change on every launch, so they are written directly into
+    /// the web app file, without loading the whole storage or creating a backup.
+    /// The storage lock is acquired for the update, so concurrent launches do not
+    /// overwrite each other.
+    pub fn update_usage<F>(dirs: &ProjectDirs, id: &Ulid, update: F) -> Result<()>
+    where
+        F: FnOnce(&mut SiteUsage),
+    {
+        let _lock = StorageLock::acquire(dirs)?;
+
+        layout::update_site(dirs, id, |site| {
+            let mut usage: SiteUsage = match site.remove("usage") {
+                Some(usage) => serde_json::from_value(usage).unwrap_or_default(),
+                None => SiteUsage::default(),
+            };
+
+            update(&mut usage);
+            site.insert("usage".into(), serde_json::to_value(usage)?);
+            Ok(())
+        })
+        .context(STORAGE_SAVE_ERROR)
+    }
+
+    /// Runs the function while holding the storage lock.
+    ///
+    /// Used for files outside the storage that are shared between processes,
+    /// such as running instances, so they are not modified concurrently.
+    pub fn with_lock<F, T>(dirs: &ProjectDirs, function: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let _lock = StorageLock::acquire(dirs)?;
+        function()
+    }
+
+    /// Writes the storage.
+    ///
+    /// Only profiles and web apps that have changed are written, and all files are
+    /// written atomically. Nothing is written if the storage has not changed.
+    /// See [`layout::read_document`](layout) for details about the storage layout.
+    ///
+    /// The previous version of the storage is kept as a backup before it is first
+    /// changed by this process. See [`backups::create`] for details.
+    pub fn write(&self, dirs: &ProjectDirs) -> Result<()> {
+        if self.partial {
+            bail!("Partially loaded storage cannot be written");
+        }
+
+        // Acquire a temporary lock if the storage is not locked anymore
+        let _lock = match self.lock {
+            Some(_) => None,
+            None => Some(StorageLock::acquire(dirs)?),
+        };
+
+        // Options that follow the policy are not stored, so policy changes still affect them
+        let mut document = serde_json::to_value(self).context(STORAGE_SAVE_ERROR)?;
+        self.policy.strip(&mut document);
+
+        // Keep previous versions, so accidental changes can be undone
+        let backup = || backups::create(dirs).context("Failed to back up storage");
+        layout::write(dirs, document, &self.hashes, backup).context(STORAGE_SAVE_ERROR)
+    }
+}
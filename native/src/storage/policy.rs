@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use cfg_if::cfg_if;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use url::Url;
+use web_app_manifest::types::Url as ManifestUrl;
+
+use crate::components::site::{SiteConfig, SiteManifest};
+use crate::directories::ProjectDirs;
+use crate::storage::Config;
+
+const POLICY_LOAD_ERROR: &str = "Failed to load policy file";
+
+/// Contains a web app that should be preinstalled for all users.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PolicySite {
+    /// Direct URL of the site's web app manifest.
+    pub manifest_url: Url,
+
+    /// Direct URL of the site's main document.
+    ///
+    /// Defaults to the result of parsing a manifest URL with `.`.
+    #[serde(default)]
+    pub document_url: Option<Url>,
+}
+
+/// System-wide defaults and restrictions set by the administrator.
+///
+/// Loaded from `/etc/firefoxpwa/policies.json` (only on Unix-like systems)
+/// or `policies.json` in the system data directory, whichever is found first.
+/// Policies are merged under the user storage, so users can still overwrite
+/// defaults, except config options that are locked by the policy.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Default config options.
+    ///
+    /// Used for options that the user has not changed.
+    /// May only contain some options, others use the normal defaults.
+    pub config: Map<String, Value>,
+
+    /// Config options that cannot be changed by the user.
+    ///
+    /// Locked options always have the value from the policy config,
+    /// or the normal default if they are not set there.
+    pub locked: Vec<String>,
+
+    /// Arguments to be passed to the Firefox runtime.
+    ///
+    /// Passed before all user arguments.
+    pub arguments: Vec<String>,
+
+    /// Environment variables to be passed to the Firefox runtime.
+    ///
+    /// May be overwritten by user variables with the same name.
+    pub variables: BTreeMap<String, String>,
+
+    /// Origins of sites that can be installed.
+    ///
+    /// If empty, all origins that are not denied can be installed.
+    pub allowed_origins: Vec<String>,
+
+    /// Origins of sites that cannot be installed.
+    ///
+    /// Take precedence over the allowed origins.
+    pub denied_origins: Vec<String>,
+
+    /// Web apps that should be preinstalled for all users.
+    ///
+    /// They are installed once when the native program first runs after they
+    /// are added to the policy, so users can still uninstall them later.
+    /// The consistency checker reports those that are not installed.
+    pub sites: Vec<PolicySite>,
+}
+
+impl Policy {
+    fn locations(dirs: &ProjectDirs) -> Vec<PathBuf> {
+        let location = dirs.sysdata.join("policies.json");
+
+        cfg_if! {
+            if #[cfg(unix)] {
+                vec![PathBuf::from("/etc/firefoxpwa/policies.json"), location]
+            } else {
+                vec![location]
+            }
+        }
+    }
+
+    /// Loads the policy, or returns an empty policy if no policy file exists.
+    pub fn load(dirs: &ProjectDirs) -> Result<Self> {
+        let filename = match Self::locations(dirs).into_iter().find(|path| path.exists()) {
+            Some(filename) => filename,
+            None => return Ok(Self::default()),
+        };
+
+        let data = read_to_string(&filename).context(POLICY_LOAD_ERROR)?;
+        let policy: Self = serde_json::from_str(&data).context(POLICY_LOAD_ERROR)?;
+
+        // Make sure the policy config is valid, so it does not break loading the storage
+        serde_json::from_value::<Config>(Value::Object(policy.config.clone()))
+            .context(POLICY_LOAD_ERROR)?;
+
+        Ok(policy)
+    }
+
+    /// Merges the policy config under the user config in the storage document.
+    ///
+    /// Options that the user has not set are taken from the policy config,
+    /// and locked options always use the value from the policy.
+    pub fn apply(&self, document: &mut Value) {
+        let document = match document.as_object_mut() {
+            Some(document) => document,
+            None => return,
+        };
+
+        let config = document.entry("config").or_insert_with(|| Value::Object(Map::new()));
+        let config = match config.as_object_mut() {
+            Some(config) => config,
+            None => return,
+        };
+
+        for key in &self.locked {
+            config.remove(key);
+        }
+
+        for (key, value) in &self.config {
+            config.entry(key).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Removes options that follow the policy from the serialized storage.
+    ///
+    /// Locked options and options with the same value as the policy default
+    /// are not stored, so they keep following the policy when it changes.
+    pub fn strip(&self, document: &mut Value) {
+        let config = match document.get_mut("config").and_then(Value::as_object_mut) {
+            Some(config) => config,
+            None => return,
+        };
+
+        config
+            .retain(|key, value| !self.locked.contains(key) && self.config.get(key) != Some(value));
+    }
+
+    /// Checks that the new config does not change any locked options.
+    pub fn check_config(&self, current: &Config, new: &Config) -> Result<()> {
+        let current = serde_json::to_value(current)?;
+        let new = serde_json::to_value(new)?;
+
+        for key in &self.locked {
+            if current.get(key) != new.get(key) {
+                bail!("Config option `{}` is locked by the policy", key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that sites from the URL origin can be installed.
+    ///
+    /// Data URLs have an opaque origin, so they are not allowed
+    /// when the policy only allows specific origins.
+    pub fn check_url(&self, url: &Url) -> Result<()> {
+        let origin = url.origin().ascii_serialization();
+        let matches = |origins: &[String]| {
+            origins.iter().any(|allowed| allowed.trim_end_matches('/') == origin)
+        };
+
+        if matches(&self.denied_origins) {
+            bail!("Origin {} is denied by the policy", origin);
+        }
+
+        if !self.allowed_origins.is_empty() && !matches(&self.allowed_origins) {
+            bail!("Origin {} is not allowed by the policy", origin);
+        }
+
+        Ok(())
+    }
+
+    /// Checks that all URLs of the processed web app can be installed.
+    ///
+    /// The manifest may use a start URL or scope from a different origin than
+    /// the URLs it was installed from, so they are checked after it has been
+    /// downloaded. Data manifest URLs are skipped, as their members are resolved
+    /// against the document URL, which is checked instead.
+    pub fn check_site(&self, config: &SiteConfig, manifest: &SiteManifest) -> Result<()> {
+        let mut urls = vec![&config.document_url];
+        urls.extend(config.start_url.as_ref());
+
+        if config.manifest_url.scheme() != "data" {
+            urls.push(&config.manifest_url);
+        }
+
+        for url in [&manifest.start_url, &manifest.scope].iter() {
+            if let ManifestUrl::Absolute(url) = url {
+                urls.push(url);
+            }
+        }
+
+        for url in urls {
+            self.check_url(url)?;
+        }
+
+        Ok(())
+    }
+}